@@ -0,0 +1,210 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::config::Config;
+use failure::Error;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use structopt::StructOpt;
+use toml::Value;
+
+/// Prefix of the exported environment variables
+const ENV_PREFIX: &str = "FIFTYSHADES";
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Prints the loaded configuration, as TOML or environment variables
+    #[structopt(name = "export")]
+    Export(ExportArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportArgs {
+    /// Print FIFTYSHADES_* variable assignments instead of TOML, for reference
+    /// only; 50shades does not read them back
+    #[structopt(long)]
+    env: bool,
+}
+
+/// Upper-cases `key` and replaces anything but letters and digits with `_`.
+fn env_name(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Flattens `value` into variables named after the path to each scalar.
+/// Arrays of scalars become comma-separated values.
+fn flatten(name: &str, value: &Value, variables: &mut Vec<(String, String)>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table.iter() {
+                flatten(&format!("{}_{}", name, env_name(key)), value, variables);
+            }
+        }
+        Value::Array(array) => {
+            if array.iter().any(Value::is_table) {
+                for (i, value) in array.iter().enumerate() {
+                    flatten(&format!("{}_{}", name, i), value, variables);
+                }
+            } else {
+                let values: Vec<String> = array.iter().map(scalar).collect();
+                variables.push((name.to_owned(), values.join(",")));
+            }
+        }
+        value => variables.push((name.to_owned(), scalar(value))),
+    }
+}
+
+/// Names that several configuration keys map to, e.g. both `prod-eu` and
+/// `prod_eu`
+fn collisions(variables: &[(String, String)]) -> Vec<&str> {
+    let mut counts = BTreeMap::new();
+    for (name, _) in variables.iter() {
+        *counts.entry(name.as_str()).or_insert(0) += 1;
+    }
+
+    counts
+        .into_iter()
+        .filter(|&(_, count)| count > 1)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Quotes `value` for POSIX shells and env files.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn export_env(config: &Config) -> Result<(), Error> {
+    let mut variables = Vec::new();
+    flatten(ENV_PREFIX, &Value::try_from(config)?, &mut variables);
+
+    for name in collisions(&variables) {
+        eprintln!(
+            "Warning: several configuration keys are exported as {}",
+            name
+        );
+    }
+
+    let referenced: BTreeSet<&str> = variables
+        .iter()
+        .flat_map(|(_, value)| config::variables(value))
+        .collect();
+
+    for (name, value) in variables.iter() {
+        println!("{}={}", name, quote(value));
+    }
+
+    if !referenced.is_empty() {
+        println!("# Environment variables referenced by the configuration:");
+        for name in referenced {
+            let state = if env::var_os(name).is_some() {
+                "set"
+            } else {
+                "not set"
+            };
+            println!("# {} ({})", name, state);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn run(config: Result<Config, Error>, command: Command) -> Result<(), Error> {
+    let config = config?;
+
+    match command {
+        Command::Export(ExportArgs { env: true }) => export_env(&config),
+        Command::Export(ExportArgs { env: false }) => {
+            print!("{}", toml::to_string(&config)?);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{collisions, flatten, quote};
+
+    #[test]
+    fn test_flatten() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            [nodes.prod-eu]
+            url = 'https://graylog.example.com/api'
+            type = 'graylog'
+            headers = { X-Tenant = '${TENANT}' }
+
+            [templates]
+            default = "{{{message}}}"
+
+            [[views]]
+            fields = ['a', 'b']
+            "#,
+        )
+        .unwrap();
+
+        let mut variables = Vec::new();
+        flatten("FIFTYSHADES", &value, &mut variables);
+
+        assert_eq!(
+            variables,
+            vec![
+                (
+                    String::from("FIFTYSHADES_NODES_PROD_EU_HEADERS_X_TENANT"),
+                    String::from("${TENANT}")
+                ),
+                (
+                    String::from("FIFTYSHADES_NODES_PROD_EU_TYPE"),
+                    String::from("graylog")
+                ),
+                (
+                    String::from("FIFTYSHADES_NODES_PROD_EU_URL"),
+                    String::from("https://graylog.example.com/api")
+                ),
+                (
+                    String::from("FIFTYSHADES_TEMPLATES_DEFAULT"),
+                    String::from("{{{message}}}")
+                ),
+                (
+                    String::from("FIFTYSHADES_VIEWS_0_FIELDS"),
+                    String::from("a,b")
+                ),
+            ]
+        );
+
+        assert!(collisions(&variables).is_empty());
+        variables.push((String::from("FIFTYSHADES_TEMPLATES_DEFAULT"), String::new()));
+        assert_eq!(
+            collisions(&variables),
+            vec!["FIFTYSHADES_TEMPLATES_DEFAULT"]
+        );
+
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
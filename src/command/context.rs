@@ -0,0 +1,173 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::config::{Config, Node};
+use crate::datetime;
+use crate::datetime::{Precision, Span};
+use crate::output;
+use crate::output::{Output, Record};
+use crate::query::{graylog, Client, RequestOptions, UnsupportedError};
+use crate::template;
+use chrono::prelude::*;
+use failure::{Error, Fail};
+use maplit::hashmap;
+use serde_json::Value;
+use std::collections::HashSet;
+use structopt::StructOpt;
+
+#[derive(Debug, Fail)]
+#[fail(display = "Message {} has no timestamp to search around", _0)]
+pub struct MissingTimestampError(String);
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Number of messages to show before the message
+    #[structopt(long, short = "B", default_value = "10")]
+    before: usize,
+
+    /// Number of messages to show after the message
+    #[structopt(long, short = "A", default_value = "10")]
+    after: usize,
+
+    /// How far to search for context on either side (e.g. 30m or 1h)
+    #[structopt(long, default_value = "1h")]
+    window: Span,
+
+    #[structopt(flatten)]
+    output: output::Args,
+
+    #[structopt(name = "MESSAGE-ID")]
+    id: String,
+
+    #[structopt(name = "INDEX")]
+    index: String,
+}
+
+/// Up to `limit` messages of `stream` between `from` and `to`, closest to the
+/// message first as given by `order`
+fn neighbours(
+    client: &Client,
+    stream: Option<&str>,
+    (from, to): (&DateTime<Utc>, &DateTime<Utc>),
+    order: &str,
+    limit: usize,
+) -> Result<Vec<Record>, Error> {
+    let mut params = hashmap! {
+        "query" => String::from("*"),
+        "from" => datetime::format(from, Precision::Millis),
+        "to" => datetime::format(to, Precision::Millis),
+        // One more, as the message itself is part of both ranges
+        "limit" => (limit + 1).to_string(),
+        "sort" => format!("timestamp:{}", order),
+    };
+
+    if let Some(stream) = stream {
+        params.insert("filter", format!("streams:{}", stream));
+    }
+
+    // fetch reverses Graylog's order; restore it to get the closest first
+    let mut records = graylog::fetch(client, &params)?;
+    records.reverse();
+    Ok(records)
+}
+
+pub fn run(
+    config: Result<Config, Error>,
+    node_name: String,
+    template: String,
+    options: &RequestOptions,
+    args: Args,
+) -> Result<(), Error> {
+    let (node, template, variants, script) = match config {
+        Ok(ref config) => (
+            config::node(config, &node_name)?,
+            config::template(config, &template)?,
+            args.output.variants(config)?,
+            config.script.as_ref(),
+        ),
+        Err(e) => return Err(e),
+    };
+
+    let node = match node {
+        Node::Graylog(node) => node,
+        Node::Elastic(_) => return Err(UnsupportedError("Message context", "Elasticsearch").into()),
+    };
+
+    let mut output = Output::new(&args.output, template::compile(template, script)?)?;
+    output.set_variants(variants);
+    output.set_severity_map(node.severity_map.clone());
+
+    let message = graylog::message(&graylog::message_client(
+        node,
+        &node_name,
+        &args.index,
+        &args.id,
+        options,
+    )?)?;
+
+    let timestamp = message
+        .get("timestamp")
+        .and_then(Value::as_str)
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok_or_else(|| MissingTimestampError(args.id.clone()))?;
+    let stream = message
+        .get("streams")
+        .and_then(|streams| streams.get(0))
+        .and_then(Value::as_str);
+
+    let window = chrono::Duration::from_std(args.window.0)?;
+    let client = graylog::node_client(node, &node_name, options)?;
+
+    let mut seen = HashSet::new();
+    seen.insert(args.id.clone());
+    let mut unseen = |record: &Record| match record.get("_id").and_then(Value::as_str) {
+        Some(id) => seen.insert(id.to_owned()),
+        None => true,
+    };
+
+    let mut before: Vec<Record> = neighbours(
+        &client,
+        stream,
+        (&(timestamp - window), &timestamp),
+        "desc",
+        args.before,
+    )?
+    .into_iter()
+    .filter(|record| unseen(record))
+    .take(args.before)
+    .collect();
+    before.reverse();
+
+    let after: Vec<Record> = neighbours(
+        &client,
+        stream,
+        (&timestamp, &(timestamp + window)),
+        "asc",
+        args.after,
+    )?
+    .into_iter()
+    .filter(|record| unseen(record))
+    .take(args.after)
+    .collect();
+
+    for record in before.iter().chain(Some(&message)).chain(after.iter()) {
+        output.print(record)?;
+    }
+
+    output.finish()
+}
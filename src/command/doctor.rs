@@ -0,0 +1,150 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::config::{Config, Node};
+use crate::password;
+use crate::query::{http_client, send, RequestOptions};
+use crate::template;
+use failure::{Error, Fail};
+use url::Url;
+
+#[derive(Debug, Fail)]
+#[fail(display = "{} of {} checks failed", _0, _1)]
+pub struct DoctorError(usize, usize);
+
+/// Tally of the checks run so far
+#[derive(Default)]
+struct Checklist {
+    passed: usize,
+    failed: usize,
+}
+
+impl Checklist {
+    /// Prints the outcome of a check, with `hint` on how to fix a failure.
+    fn check(&mut self, name: &str, result: Result<(), Error>, hint: &str) {
+        match result {
+            Ok(()) => {
+                self.passed += 1;
+                println!("[ok]   {}", name);
+            }
+            Err(e) => {
+                self.failed += 1;
+                println!("[FAIL] {}", name);
+                for line in e.to_string().lines().filter(|line| !line.is_empty()) {
+                    println!("       {}", line);
+                }
+                println!("       Fix: {}", hint);
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        let total = self.passed + self.failed;
+
+        if self.failed == 0 {
+            println!("All {} checks passed.", total);
+            Ok(())
+        } else {
+            Err(DoctorError(self.failed, total).into())
+        }
+    }
+}
+
+/// Whether anything answers HTTP at the node's URL; any status will do, as
+/// requests are not authenticated here
+fn reachable(node: &Node) -> Result<(), Error> {
+    let mut url = Url::parse(node.url())?;
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+
+    let options = RequestOptions::default();
+    send(&options, http_client(&options)?.get(url.as_str()), None)?;
+    Ok(())
+}
+
+/// Whether the password for the node's user can be obtained, if it has one
+fn password(name: &str, node: &Node) -> Result<(), Error> {
+    if !Url::parse(node.url())?.username().is_empty() {
+        return Ok(());
+    }
+
+    let user = match node {
+        Node::Graylog(node) => Some(&node.user),
+        Node::Elastic(node) => node.user.as_ref(),
+    };
+
+    match user {
+        Some(user) => password::get(name, user).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+pub fn run(config: Result<Config, Error>, path: &str, template: &str) -> Result<(), Error> {
+    let mut checklist = Checklist::default();
+
+    let config = match config {
+        Ok(config) => {
+            checklist.check(&format!("Configuration file {}", path), Ok(()), "");
+            config
+        }
+        Err(e) => {
+            checklist.check(
+                &format!("Configuration file {}", path),
+                Err(e),
+                "run `50shades init` to create it, or `50shades migrate` if it is outdated",
+            );
+            return checklist.finish();
+        }
+    };
+
+    let mut names: Vec<&String> = config.nodes.keys().collect();
+    names.sort();
+
+    for name in names {
+        let node = &config.nodes[name];
+
+        checklist.check(
+            &format!("Node {} is reachable at {}", name, node.url()),
+            reachable(node),
+            "check the URL, network access and TLS certificates",
+        );
+        checklist.check(
+            &format!("Credentials for node {} are available", name),
+            password(name, node),
+            &format!("run `50shades --node {} login`", name),
+        );
+    }
+
+    let mut script = None;
+    if let Some(path) = config::helper_script(&config, path) {
+        checklist.check(
+            &format!("Helper script {} runs", path.display()),
+            template::load_script(&path).map(|loaded| script = Some(loaded)),
+            "check the script for syntax errors",
+        );
+    }
+
+    checklist.check(
+        &format!("Template {} compiles", template),
+        config::template(&config, template)
+            .map_err(Error::from)
+            .and_then(|template| template::compile(template, script.as_ref()).map(|_| ())),
+        "test it with `50shades template check --sample <record.json>`",
+    );
+
+    checklist.finish()
+}
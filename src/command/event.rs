@@ -0,0 +1,115 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::config::{Config, Node};
+use crate::output;
+use crate::output::Output;
+use crate::query::{graylog, scope, RequestOptions, UnsupportedError};
+use crate::template;
+use failure::{Error, Fail};
+use std::collections::HashMap;
+use structopt::StructOpt;
+
+#[derive(Debug, Fail)]
+#[fail(display = "Event {} has no time range to search", _0)]
+pub struct EventRangeError(String);
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    output: output::Args,
+
+    #[structopt(name = "EVENT-ID")]
+    id: String,
+}
+
+pub fn run(
+    config: Result<Config, Error>,
+    node_name: String,
+    template: String,
+    options: &RequestOptions,
+    args: Args,
+) -> Result<(), Error> {
+    let (node, template, variants, script) = match config {
+        Ok(ref config) => (
+            config::node(config, &node_name)?,
+            config::template(config, &template)?,
+            args.output.variants(config)?,
+            config.script.as_ref(),
+        ),
+        Err(e) => return Err(e),
+    };
+
+    let node = match node {
+        Node::Graylog(node) => node,
+        Node::Elastic(_) => return Err(UnsupportedError("Events", "Elasticsearch").into()),
+    };
+
+    let mut output = Output::new(&args.output, template::compile(template, script)?)?;
+    output.set_variants(variants);
+    output.set_severity_map(node.severity_map.clone());
+
+    let event = graylog::event(&graylog::event_client(node, &node_name, options)?, &args.id)?;
+    let definition = graylog::definition(&graylog::definition_client(
+        node,
+        &node_name,
+        &event.event_definition_id,
+        options,
+    )?)?;
+
+    let (from, to) = match (event.timerange_start, event.timerange_end) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return Err(EventRangeError(event.id).into()),
+    };
+
+    let streams = if event.source_streams.is_empty() {
+        definition.config.streams
+    } else {
+        event.source_streams
+    };
+
+    let mut query = Vec::new();
+    if !definition.config.query.is_empty() {
+        query.push(definition.config.query);
+    }
+
+    if !streams.is_empty() {
+        query = scope(Some(&format!("streams:({})", streams.join(" OR "))), &query);
+    }
+
+    eprintln!(
+        "{}: {} ({} to {})",
+        definition.title, event.message, from, to
+    );
+
+    let mut params = HashMap::new();
+    graylog::assign_query(&query, &mut params);
+    params.insert("limit", "0".into());
+    params.insert("from", from);
+    params.insert("to", to);
+
+    graylog::run(
+        &graylog::node_client(node, &node_name, options)?,
+        &params,
+        false,
+        false,
+        None,
+        &mut output,
+    )?;
+
+    output.finish()
+}
@@ -17,73 +17,505 @@
 use crate::config;
 use crate::config::{Config, ElasticNode, GraylogNode, Node};
 use crate::datetime;
-use crate::query::{elastic, graylog};
+use crate::datetime::{Precision, Span};
+use crate::output;
+use crate::output::{Condition, Output, Record};
+use crate::query;
+use crate::query::{
+    elastic, graylog, scope, EmptyQueryError, RequestOptions, ResponseError, UnsupportedError,
+};
+use crate::severity::Severity;
 use crate::template;
 use chrono::prelude::*;
-use failure::Error;
+use failure::{Error, Fail};
 use handlebars::Handlebars;
 use maplit::hashmap;
-use std::collections::HashMap;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::mem;
 use std::ops::Sub;
+use std::process;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime};
 use std::{thread, time};
+use structopt::StructOpt;
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "--format {} needs --duration, --max-records or --search-to when following",
+    _0
+)]
+pub struct UnboundedFormatError(String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "--tui needs --duration, --max-records or --search-to when following")]
+pub struct UnboundedTuiError;
+
+const DEFAULT_FROM: &str = "10 seconds ago";
+const DEFAULT_LATENCY: i64 = 2;
+const DEFAULT_POLL: u64 = 1000;
+/// Longest sleep between checks for an interrupt while waiting for a poll
+const INTERRUPT_CHECK: time::Duration = time::Duration::from_millis(100);
+/// Wait before retrying a search after the first transient error, doubled
+/// with every further one up to `RETRY_MAX`
+const RETRY_START: time::Duration = time::Duration::from_secs(1);
+const RETRY_MAX: time::Duration = time::Duration::from_secs(60);
+
+/// Set on Ctrl-C, see `handle_interrupt`
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes Ctrl-C stop following after the current search, so output is not cut
+/// off mid-line. The handler resets itself, so a second Ctrl-C still kills.
+#[cfg(unix)]
+fn handle_interrupt() -> Result<(), Error> {
+    let handler: extern "C" fn(libc::c_int) = interrupt;
+
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART | libc::SA_RESETHAND;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn handle_interrupt() -> Result<(), Error> {
+    Ok(())
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Sleeps for `duration`, returning early with false on Ctrl-C.
+fn sleep(duration: time::Duration) -> bool {
+    let woken = Instant::now() + duration;
+
+    loop {
+        let now = Instant::now();
+        if now >= woken {
+            break;
+        }
+        if interrupted() {
+            return false;
+        }
+        thread::sleep((woken - now).min(INTERRUPT_CHECK));
+    }
+
+    true
+}
+
+/// How long to wait before the next retry after `failures` transient errors
+/// in a row
+fn backoff(failures: u32) -> time::Duration {
+    RETRY_START
+        .checked_mul(2u32.saturating_pow(failures))
+        .map_or(RETRY_MAX, |delay| delay.min(RETRY_MAX))
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Start of the search range [default: 10 seconds ago, or the configured search-from]
+    #[structopt(long = "search-from", short = "@")]
+    from: Option<String>,
+
+    /// Stop following once the search range reaches this time, after a final search
+    #[structopt(long = "search-to", short = "#")]
+    to: Option<String>,
+
+    /// Seconds to stay behind the present [default: 2, or the configured latency]
+    #[structopt(long)]
+    latency: Option<i64>,
+
+    /// Milliseconds between searches [default: 1000, or the configured poll]
+    #[structopt(long)]
+    poll: Option<u64>,
+
+    /// Fractional second precision of the search range (millis, micros or nanos)
+    #[structopt(long, default_value = "millis")]
+    precision: Precision,
+
+    /// Stop following after this long (e.g. 30m or 1h)
+    #[structopt(long)]
+    duration: Option<Span>,
+
+    /// Stop following after this many records have been output, defaults to the
+    /// configured max-records (or limit)
+    #[structopt(long = "max-records")]
+    max_records: Option<u64>,
+
+    /// Milliseconds to wait between requesting pages of large results
+    #[structopt(long = "page-delay", default_value = "0")]
+    page_delay: u64,
+
+    #[structopt(flatten)]
+    output: output::Args,
+
+    #[structopt(flatten)]
+    elastic: elastic::Args,
+
+    #[structopt(flatten)]
+    graylog: graylog::Args,
+
+    /// Start right after the newest matching record instead of --search-from
+    #[structopt(long = "from-latest")]
+    from_latest: bool,
+
+    /// Ring the terminal bell when a record with field=value arrives
+    #[structopt(long)]
+    watch: Option<Condition>,
+
+    /// Shell command to run for each --watch match, given the record as JSON on stdin
+    #[structopt(long = "on-match", requires = "watch")]
+    on_match: Option<String>,
+
+    /// Give up after this many transient search errors in a row [default: never]
+    #[structopt(long = "max-retries")]
+    max_retries: Option<u32>,
+
+    /// Stop following at the first --watch match and exit with this code
+    #[structopt(long = "exit-on-match", requires = "watch")]
+    exit_on_match: Option<i32>,
+
+    /// Search this much (e.g. 5s) before each poll's start again, skipping hits
+    /// already output, to catch Elasticsearch documents made searchable late
+    #[structopt(long)]
+    overlap: Option<Span>,
+
+    /// Pick up node and template changes from the configuration file
+    #[structopt(long = "watch-config")]
+    watch_config: bool,
+
+    /// Fail on an empty query instead of matching all messages
+    #[structopt(long = "no-default-query")]
+    no_default_query: bool,
+
+    /// Ignore the node's base query
+    #[structopt(long = "no-base-query")]
+    no_base_query: bool,
+
+    #[structopt(name = "QUERY")]
+    query: Vec<String>,
+}
+
+/// Configuration file modification time, polled for --watch-config
+struct Watch {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl Watch {
+    fn new(path: &str) -> Self {
+        Watch {
+            path: path.to_owned(),
+            modified: Watch::modified(path),
+        }
+    }
+
+    fn modified(path: &str) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+
+    fn changed(&mut self) -> bool {
+        let modified = Watch::modified(&self.path);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+/// IDs of the records output by the previous poll. Graylog's absolute
+/// searches include both ends, so records right at the end of one search
+/// range are found again at the start of the next.
+#[derive(Default)]
+struct Boundary {
+    previous: HashSet<String>,
+    current: HashSet<String>,
+}
+
+impl Boundary {
+    /// Whether `record` was output by the previous poll, remembering it for
+    /// the next one otherwise.
+    fn repeated(&mut self, record: &Record) -> bool {
+        match record.get("_id").and_then(Value::as_str) {
+            Some(id) if self.previous.contains(id) => true,
+            Some(id) => {
+                self.current.insert(id.to_owned());
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Makes the records of the finished poll the ones to skip in the next.
+    fn next(&mut self) {
+        self.previous = mem::replace(&mut self.current, HashSet::new());
+    }
+}
+
+enum Next {
+    Poll,
+    Reload,
+    Stop,
+}
+
+struct Session {
+    started: Instant,
+    watch: Option<Watch>,
+    exit: Option<i32>,
+    /// IDs of Elasticsearch hits output by the last poll, and by those within
+    /// --overlap, with the end of the search range they were found in
+    seen: HashMap<String, DateTime<Utc>>,
+    /// Graylog records output by the previous poll
+    boundary: Boundary,
+    /// End of the last search range, given by --search-to
+    to: Option<DateTime<Utc>>,
+    /// Transient search errors in a row
+    failures: u32,
+    /// Options of every search made while following
+    options: RequestOptions,
+}
+
+/// Runs `command`, passing `record` as JSON on stdin.
+fn on_match(command: &str, record: &Record) -> Result<(), Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{}", serde_json::Value::from(record.clone()))?;
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        eprintln!("Command {} failed: {}", command, status);
+    }
+
+    Ok(())
+}
+
+impl Session {
+    /// Acts on records matching --watch, returning whether to stop following.
+    fn alert(&mut self, args: &Args, output: &mut Output) -> Result<bool, Error> {
+        let matches = output.take_matches();
+        if matches.is_empty() {
+            return Ok(false);
+        }
+
+        eprint!("\x07");
+
+        if let Some(ref command) = args.on_match {
+            for record in matches.iter() {
+                on_match(command, record)?;
+            }
+        }
+
+        self.exit = args.exit_on_match;
+        Ok(self.exit.is_some())
+    }
+
+    /// Whether the hit `record` was found before, remembering it otherwise.
+    /// `_meta` is only kept with --hit-meta.
+    fn seen(&mut self, record: &mut Record, until: DateTime<Utc>, args: &Args) -> bool {
+        let meta = if args.elastic.hit_meta {
+            record.get("_meta").cloned()
+        } else {
+            record.remove("_meta")
+        };
+
+        match meta
+            .as_ref()
+            .and_then(|meta| meta.get("_id"))
+            .and_then(Value::as_str)
+        {
+            Some(id) => self.seen.insert(id.to_owned(), until).is_some(),
+            None => false,
+        }
+    }
+
+    /// Forgets hits too old to be found again by searches from `from` on,
+    /// keeping those found up to `from`, which the next search starts at.
+    fn forget(&mut self, from: DateTime<Utc>, overlap: chrono::Duration) {
+        self.seen.retain(|_, until| *until >= from - overlap);
+    }
+
+    /// Passes on the outcome of a search, but waits and returns None instead
+    /// for errors worth retrying, until --max-retries is exhausted.
+    fn retry<T>(&mut self, result: Result<T, Error>, args: &Args) -> Result<Option<T>, Error> {
+        let error = match result {
+            Ok(value) => {
+                self.failures = 0;
+                return Ok(Some(value));
+            }
+            Err(error) => error,
+        };
+
+        let transient = error
+            .downcast_ref::<ResponseError>()
+            .map_or(false, ResponseError::is_transient);
+        if !transient || args.max_retries.map_or(false, |max| self.failures >= max) {
+            return Err(error);
+        }
+
+        let delay = backoff(self.failures);
+        self.failures += 1;
+        eprintln!("Search failed, retrying in {}s: {}", delay.as_secs(), error);
+        sleep(delay);
+        Ok(None)
+    }
+
+    /// End of the next search range, `latency` seconds behind the present, and
+    /// whether it is the last one as it reached --search-to.
+    fn until(&self, latency: i64) -> (DateTime<Utc>, bool) {
+        let now = Utc::now().sub(chrono::Duration::seconds(latency));
+
+        match self.to {
+            Some(to) if now >= to => (to, true),
+            _ => (now, false),
+        }
+    }
+
+    /// Sleeps until the next poll, unless following should stop.
+    fn wait(&mut self, args: &Args, output: &Output) -> Next {
+        if output.exhausted() || interrupted() {
+            return Next::Stop;
+        }
+
+        let mut sleep = time::Duration::from_millis(args.poll.unwrap_or(DEFAULT_POLL));
+
+        if let Some(Span(duration)) = args.duration {
+            match duration.checked_sub(self.started.elapsed()) {
+                Some(remaining) if remaining > sleep => (),
+                Some(remaining) if remaining > time::Duration::from_secs(0) => sleep = remaining,
+                _ => return Next::Stop,
+            }
+        }
+
+        if !self::sleep(sleep) {
+            return Next::Stop;
+        }
+
+        if self.watch.as_mut().map_or(false, Watch::changed) {
+            Next::Reload
+        } else {
+            Next::Poll
+        }
+    }
+}
 
 fn follow_graylog(
     node: &GraylogNode,
     node_name: &str,
-    handlebars: &Handlebars,
-    from: &str,
-    latency: i64,
-    poll: u64,
     query: &[String],
-) -> Result<(), Error> {
-    let client = graylog::node_client(&node, node_name)?;
+    mut from: String,
+    session: &mut Session,
+    output: &mut Output,
+    args: &Args,
+) -> Result<Option<String>, Error> {
+    let client = graylog::node_client(&node, node_name, &session.options)?;
 
     let mut params = HashMap::new();
-    let mut from = datetime::parse_timestamp(&from)?.0;
-    let sleep = time::Duration::from_millis(poll);
-    graylog::assign_query(&query, &mut params);
+    let extra = args.graylog.params();
+    graylog::assign_query(query, &mut params);
 
     loop {
-        let now = &Utc::now()
-            .sub(chrono::Duration::seconds(latency))
-            .to_rfc3339_opts(SecondsFormat::Millis, true);
+        if interrupted() {
+            return Ok(None);
+        }
+
+        let (until, last) = session.until(args.latency.unwrap_or(DEFAULT_LATENCY));
+        let now = &datetime::format(&until, args.precision);
 
         params.insert("limit", "0".into());
-        params.insert("from", from);
+        params.insert("from", from.clone());
         params.insert("to", String::from(now));
+        params.extend(extra.clone());
 
-        graylog::run(&client, &params, &handlebars)?;
+        let records = graylog::records(
+            &client,
+            &params,
+            args.graylog.common_fields,
+            args.graylog.highlighted(&args.output)?,
+            None,
+        );
+        // A failed search is repeated from the same start, closing the gap
+        let records = match session.retry(records, args)? {
+            Some(records) => records,
+            None => continue,
+        };
+
+        for record in records.iter() {
+            if !session.boundary.repeated(record) {
+                output.print(record)?;
+            }
+        }
+        session.boundary.next();
 
         from = String::from(now);
-        thread::sleep(sleep);
+        output.flush()?;
+        if session.alert(args, output)? || last {
+            return Ok(None);
+        }
+
+        match session.wait(args, output) {
+            Next::Poll => (),
+            Next::Reload => return Ok(Some(from)),
+            Next::Stop => return Ok(None),
+        }
     }
 }
 
 fn follow_elastic(
     node: &ElasticNode,
     node_name: &str,
-    handlebars: &Handlebars,
-    from: &str,
-    latency: i64,
-    poll: u64,
     query: &[String],
-) -> Result<(), Error> {
-    let client = elastic::node_client(node, &node_name)?;
-
-    let mut from = datetime::parse_timestamp(&from)?.0;
-    let sleep = time::Duration::from_millis(poll);
+    mut from: String,
+    session: &mut Session,
+    output: &mut Output,
+    args: &Args,
+) -> Result<Option<String>, Error> {
+    let client = elastic::node_client(
+        node,
+        &node_name,
+        args.elastic.index.as_ref().map(String::as_str),
+        &session.options,
+    )?;
+    let overlap = match args.overlap {
+        Some(Span(overlap)) => chrono::Duration::from_std(overlap)?,
+        None => chrono::Duration::zero(),
+    };
 
     loop {
-        let now = &Utc::now()
-            .sub(chrono::Duration::seconds(latency))
-            .to_rfc3339_opts(SecondsFormat::Millis, true);
+        if interrupted() {
+            return Ok(None);
+        }
+
+        let (until, last) = session.until(args.latency.unwrap_or(DEFAULT_LATENCY));
+        let now = &datetime::format(&until, args.precision);
+
+        let start = match args.overlap {
+            Some(_) => datetime::shift(&from, -overlap, args.precision)?,
+            None => from.clone(),
+        };
 
         let range = elastic::Query::Range(hashmap! {
-            "@timestamp".to_owned() => elastic::Range {
-                gte: Some(from),
-                lt: Some(now.to_string()),
-                ..Default::default()
-            }
+            "@timestamp".to_owned() => elastic::Range::between(start, now.to_string(), args.precision)
         });
 
         let request = elastic::Request {
@@ -104,40 +536,355 @@ fn follow_elastic(
             } else {
                 range
             },
+            source: args.elastic.source(),
+            script_fields: args.elastic.script_fields(),
+            stored_fields: args.elastic.stored_fields(),
+            docvalue_fields: args.elastic.docvalue_fields(),
+            aggs: None,
+            track_total_hits: args.elastic.track_total_hits,
+            terminate_after: args.elastic.terminate_after,
+            min_score: args.elastic.min_score,
+            collapse: args.elastic.collapse(),
+            // Hits are told apart by their ID
+            hit_meta: true,
+            annotation: None,
         };
 
-        elastic::run(&client, &request, &handlebars)?;
+        // A failed search is repeated from the same start, closing the gap
+        let records = match session.retry(elastic::fetch(&client, &request), args)? {
+            Some(records) => records,
+            None => continue,
+        };
+
+        // Hits right at the boundary of the last poll's range are skipped,
+        // just like those found again with --overlap
+        for mut record in records.into_iter() {
+            if !session.seen(&mut record, until, args) {
+                output.print(&record)?;
+            }
+        }
+        session.forget(until, overlap);
 
         from = String::from(now);
-        thread::sleep(sleep);
+        output.flush()?;
+        if session.alert(args, output)? || last {
+            return Ok(None);
+        }
+
+        match session.wait(args, output) {
+            Next::Poll => (),
+            Next::Reload => return Ok(Some(from)),
+            Next::Stop => return Ok(None),
+        }
     }
 }
 
+/// Timestamp of the newest record matching `query`, if any.
+fn latest(
+    node: &Node,
+    node_name: &str,
+    query: &[String],
+    args: &Args,
+    options: &RequestOptions,
+) -> Result<Option<String>, Error> {
+    let (records, field) = match node {
+        Node::Graylog(node) => {
+            let client = graylog::node_client(node, node_name, options)?;
+            let mut params = hashmap! {
+                "from" => datetime::format(&Utc.timestamp_millis_opt(0).unwrap(), args.precision),
+                "to" => datetime::format(&Utc::now(), args.precision),
+                "limit" => String::from("1"),
+                "sort" => String::from("timestamp:desc"),
+            };
+            graylog::assign_query(query, &mut params);
+            params.extend(args.graylog.params());
+            (graylog::fetch(&client, &params)?, "timestamp")
+        }
+        Node::Elastic(node) => {
+            let client = elastic::node_client(
+                node,
+                node_name,
+                args.elastic.index.as_ref().map(String::as_str),
+                options,
+            )?;
+            let request = elastic::Request {
+                size: Some(1),
+                sort: hashmap! {
+                    "@timestamp".to_owned() => "desc".to_owned()
+                },
+                query: elastic::Query::QueryString {
+                    query: if query.is_empty() {
+                        String::from("*")
+                    } else {
+                        query.join(" ")
+                    },
+                },
+                source: None,
+                script_fields: None,
+                stored_fields: None,
+                docvalue_fields: None,
+                aggs: None,
+                track_total_hits: None,
+                terminate_after: None,
+                min_score: None,
+                collapse: None,
+                hit_meta: false,
+                annotation: None,
+            };
+            (elastic::fetch(&client, &request)?, "@timestamp")
+        }
+    };
+
+    Ok(records
+        .first()
+        .and_then(|record| record.get(field))
+        .and_then(|timestamp| timestamp.as_str())
+        .map(String::from))
+}
+
+/// Rereads the configuration and its helper script, checking that the node and
+/// template still exist.
+fn reload(
+    path: &str,
+    node_name: &str,
+    template: &str,
+    args: &Args,
+) -> Result<(Config, Handlebars, HashMap<Severity, Handlebars>), Error> {
+    let mut config = config::read(path.to_owned())?;
+    config::node(&config, node_name)?;
+    config::load_script(&mut config, path)?;
+    let handlebars =
+        template::compile(config::template(&config, template)?, config.script.as_ref())?;
+    let variants = args.output.variants(&config)?;
+    Ok((config, handlebars, variants))
+}
+
 pub fn run(
     config: Result<Config, Error>,
+    path: &str,
     node_name: String,
     template: String,
-    from: String,
-    latency: i64,
-    poll: u64,
-    query: Vec<String>,
+    options: &RequestOptions,
+    mut args: Args,
 ) -> Result<(), Error> {
-    let (node, template) = match config {
-        Ok(ref config) => (
-            config::node(config, &node_name)?,
-            config::template(config, &template)?,
-        ),
-        Err(e) => return Err(e),
+    let mut config = config?;
+
+    let defaults = config::defaults(&config, config::node(&config, &node_name)?);
+    args.from = args.from.or(defaults.search_from);
+    args.latency = args.latency.or(defaults.latency);
+    args.poll = args.poll.or(defaults.poll);
+    args.max_records = args.max_records.or(defaults.max_records);
+
+    if args.no_default_query && args.query.is_empty() {
+        return Err(EmptyQueryError.into());
+    }
+
+    let bounded = args.duration.is_some() || args.max_records.is_some() || args.to.is_some();
+
+    let format = args.output.format()?;
+    if format.is_document() && !bounded {
+        return Err(UnboundedFormatError(format.to_string()).into());
+    }
+
+    if args.output.tui && !bounded {
+        return Err(UnboundedTuiError.into());
+    }
+
+    let severity_map = config::node(&config, &node_name)?.severity_map().clone();
+    let handlebars = template::compile(
+        config::template(&config, &template)?,
+        config.script.as_ref(),
+    )?;
+
+    handle_interrupt()?;
+
+    let mut output = Output::new(&args.output, handlebars)?;
+    if let Some(ref condition) = args.watch {
+        output.watch(condition.clone());
+    }
+    output.set_variants(args.output.variants(&config)?);
+    output.set_severity_map(severity_map);
+    if let Some(max) = args.max_records {
+        output.limit(max);
+    }
+
+    if args.output.sort_output.is_some() && !bounded {
+        eprintln!("Warning: --sort-output holds back all records until following stops");
+    }
+
+    let mut session = Session {
+        started: Instant::now(),
+        watch: Some(Watch::new(path)).filter(|_| args.watch_config),
+        exit: None,
+        seen: HashMap::new(),
+        boundary: Boundary::default(),
+        to: None,
+        failures: 0,
+        options: RequestOptions {
+            page_delay: Some(time::Duration::from_millis(args.page_delay)),
+            ..options.clone()
+        },
     };
 
-    let handlebars = template::compile(&template)?;
+    let start = args
+        .from
+        .as_ref()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_FROM);
+    let mut from = match args.to {
+        Some(ref to) => {
+            let (from, to) = datetime::parse_range(start, to, args.precision, false)?;
+            session.to = Some(DateTime::parse_from_rfc3339(&to)?.with_timezone(&Utc));
+            from
+        }
+        None => datetime::parse_timestamp(start, args.precision)?.0,
+    };
 
-    match node {
-        Node::Graylog(node) => {
-            follow_graylog(node, &node_name, &handlebars, &from, latency, poll, &query)
+    if args.from_latest {
+        let node = config::node(&config, &node_name)?;
+        let base = node.base_query().filter(|_| !args.no_base_query);
+
+        let query = scope(base, &args.query);
+        match latest(node, &node_name, &query, &args, &session.options)? {
+            Some(timestamp) => from = datetime::after(&timestamp, args.precision)?,
+            None => eprintln!("No record found, following from {}", from),
         }
-        Node::Elastic(node) => {
-            follow_elastic(node, &node_name, &handlebars, &from, latency, poll, &query)
+    }
+
+    loop {
+        let node = config::node(&config, &node_name)?;
+        query::check_options(node, &args.elastic, &args.graylog)?;
+        if let (Node::Graylog(_), Some(_)) = (node, args.overlap) {
+            return Err(UnsupportedError("--overlap", "Graylog").into());
+        }
+        let base = node.base_query().filter(|_| !args.no_base_query);
+        let query = scope(base, &args.query);
+
+        let next = match node {
+            Node::Graylog(node) => follow_graylog(
+                node,
+                &node_name,
+                &query,
+                from,
+                &mut session,
+                &mut output,
+                &args,
+            )?,
+            Node::Elastic(node) => follow_elastic(
+                node,
+                &node_name,
+                &query,
+                from,
+                &mut session,
+                &mut output,
+                &args,
+            )?,
+        };
+
+        from = match next {
+            Some(from) => from,
+            None => break,
+        };
+
+        match reload(path, &node_name, &template, &args) {
+            Ok((reloaded, handlebars, variants)) => {
+                config = reloaded;
+                output.set_template(handlebars);
+                output.set_variants(variants);
+                if let Ok(node) = config::node(&config, &node_name) {
+                    output.set_severity_map(node.severity_map().clone());
+                }
+                eprintln!("Reloaded configuration file {}.", path);
+            }
+            Err(e) => eprintln!("Keeping previous configuration: {}", e),
+        }
+    }
+
+    output.summary(&node_name, session.started.elapsed())?;
+    output.finish()?;
+
+    if let Some(code) = session.exit {
+        // Exiting skips destructors, so the output has to be closed first
+        drop(output);
+        process::exit(code);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{backoff, Args, Boundary, Session};
+    use crate::output::Record;
+    use chrono::{Duration, TimeZone, Utc};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::time::Instant;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_boundary() {
+        let batch = |ids: &[&str]| -> Vec<Record> {
+            ids.iter()
+                .map(|id| json!({ "_id": id }).as_object().unwrap().clone())
+                .collect()
+        };
+        let mut boundary = Boundary::default();
+        let mut printed = Vec::new();
+
+        for ids in [&["a", "b"][..], &["b", "c"], &["c", "d"]].iter() {
+            for record in batch(ids).iter() {
+                if !boundary.repeated(record) {
+                    printed.push(record["_id"].clone());
+                }
+            }
+            boundary.next();
         }
+
+        assert_eq!(printed, vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_seen() {
+        let args = Args::from_iter(&["follow"]);
+        let mut session = Session {
+            started: Instant::now(),
+            watch: None,
+            exit: None,
+            seen: HashMap::new(),
+            boundary: Boundary::default(),
+            to: None,
+            failures: 0,
+            options: Default::default(),
+        };
+        let mut printed = Vec::new();
+
+        for (poll, ids) in [&["a", "b"][..], &["b", "c"], &["c", "d"]]
+            .iter()
+            .enumerate()
+        {
+            let until = Utc.timestamp(poll as i64, 0);
+            for id in ids.iter() {
+                let mut record = json!({ "_meta": { "_id": id } })
+                    .as_object()
+                    .unwrap()
+                    .clone();
+                if !session.seen(&mut record, until, &args) {
+                    assert!(!record.contains_key("_meta"));
+                    printed.push(id.to_string());
+                }
+            }
+            session.forget(until, Duration::zero());
+        }
+
+        assert_eq!(printed, vec!["a", "b", "c", "d"]);
+        assert_eq!(session.seen.len(), 2);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let delays: Vec<u64> = (0..8).map(|failures| backoff(failures).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff(std::u32::MAX).as_secs(), 60);
     }
 }
@@ -0,0 +1,69 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::config::{Config, Node};
+use crate::output;
+use crate::output::Output;
+use crate::query::{graylog, RequestOptions, UnsupportedError};
+use crate::template;
+use failure::Error;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    output: output::Args,
+
+    #[structopt(name = "MESSAGE-ID")]
+    id: String,
+
+    #[structopt(name = "INDEX")]
+    index: String,
+}
+
+pub fn run(
+    config: Result<Config, Error>,
+    node_name: String,
+    template: String,
+    options: &RequestOptions,
+    args: Args,
+) -> Result<(), Error> {
+    let (node, template, variants, script) = match config {
+        Ok(ref config) => (
+            config::node(config, &node_name)?,
+            config::template(config, &template)?,
+            args.output.variants(config)?,
+            config.script.as_ref(),
+        ),
+        Err(e) => return Err(e),
+    };
+
+    let mut output = Output::new(&args.output, template::compile(template, script)?)?;
+    output.set_variants(variants);
+    output.set_severity_map(node.severity_map().clone());
+
+    match node {
+        Node::Graylog(node) => {
+            let client = graylog::message_client(node, &node_name, &args.index, &args.id, options)?;
+            graylog::get(&client, &mut output)?;
+            output.finish()
+        }
+        Node::Elastic(_) => {
+            Err(UnsupportedError("Fetching messages by ID", "Elasticsearch").into())
+        }
+    }
+}
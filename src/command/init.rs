@@ -19,6 +19,7 @@ use crate::config::{Config, ElasticNode, GraylogNode, NoConfigError, Node};
 use crate::password;
 use dialoguer::{Input, PasswordInput, Select};
 use failure::{Error, Fail};
+use std::collections::HashMap;
 use url::Url;
 
 #[derive(Debug, Fail)]
@@ -62,6 +63,11 @@ Graylog's API endpoint is usually exposed as /api, e.g. https://graylog.example.
     Node::Graylog(GraylogNode {
         user,
         url: url.to_string(),
+        base_query: None,
+        headers: HashMap::new(),
+        trace_field: None,
+        severity_map: HashMap::new(),
+        defaults: Default::default(),
     })
 }
 
@@ -103,6 +109,11 @@ fn prompt_elastic(node: &str) -> Node {
     Node::Elastic(ElasticNode {
         user,
         url: url.to_string(),
+        base_query: None,
+        headers: HashMap::new(),
+        trace_field: None,
+        severity_map: HashMap::new(),
+        defaults: Default::default(),
     })
 }
 
@@ -162,8 +173,11 @@ fn prompt(path: &str, node_name: &str) -> Result<(), Error> {
     };
 
     let config = Config {
+        defaults: Default::default(),
         nodes: vec![(node_name.to_owned(), node)].into_iter().collect(),
         templates: config::Templates::default(),
+        helpers: None,
+        script: None,
     };
 
     println!("Storing configuration...");
@@ -0,0 +1,88 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use failure::Error;
+
+/// Line-based diff of `old` and `new` using their longest common subsequence.
+fn diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        } else {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        }
+    }
+
+    lines
+}
+
+pub fn run(path: &str) -> Result<(), Error> {
+    let contents = config::read_string(path)?;
+    let config = config::parse(&contents)?;
+
+    let old = toml::to_string_pretty(&toml::from_str::<toml::Value>(&contents)?)?;
+    let new = toml::to_string_pretty(&config)?;
+
+    let changes = diff(&old, &new);
+    if changes.is_empty() {
+        println!("Configuration file {} is up to date.", path);
+        return Ok(());
+    }
+
+    for line in changes {
+        println!("{}", line);
+    }
+
+    config::write(path, &config)?;
+    println!("Migrated configuration file {}.", path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::diff;
+
+    #[test]
+    fn test_diff() {
+        assert!(diff("a\nb\n", "a\nb\n").is_empty());
+        assert_eq!(diff("a\nc\n", "a\nb\nc\n"), vec!["+ b"]);
+        assert_eq!(diff("a\nb\n", "a\nc\n"), vec!["+ c", "- b"]);
+    }
+}
@@ -17,34 +17,293 @@
 use crate::config;
 use crate::config::{Config, ElasticNode, GraylogNode, Node};
 use crate::datetime;
-use crate::query::{elastic, graylog};
+use crate::datetime::{Precision, Span};
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::output;
+use crate::output::{Output, Record};
+use crate::query;
+use crate::query::{
+    elastic, graylog, scope, Client, EmptyQueryError, RequestOptions, ResponseError,
+    UnsupportedError,
+};
+use crate::severity;
 use crate::template;
-use failure::Error;
+use chrono::prelude::*;
+use dialoguer::Confirmation;
+use failure::{Error, Fail};
 use handlebars::Handlebars;
 use maplit::hashmap;
+use serde_json::Value;
 use std::collections::HashMap;
+use std::process;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(Debug, Fail)]
+#[fail(display = "No record found in the search range to probe")]
+pub struct NoProbeRecordError;
+
+#[derive(Debug, Fail)]
+#[fail(display = "Query not confirmed")]
+pub struct NotConfirmedError;
+
+const DEFAULT_FROM: &str = "2 minutes ago";
+const DEFAULT_TO: &str = "now";
+
+/// Search ranges from which on unrestricted queries need confirmation
+const BROAD_RANGE_HOURS: i64 = 24;
+
+/// Exit code of --fail-on-empty, distinct from the 1 of errors
+const EMPTY_EXIT_CODE: i32 = 2;
+
+/// The time span searched, parsed once so that relative times mean the same
+/// for every request of a run
+enum Range {
+    Absolute(String, String),
+    /// --keyword, interpreted by Graylog
+    Keyword(String),
+}
+
+impl Range {
+    /// Start and end of an absolute range, which all but Graylog need
+    fn absolute(&self) -> Result<(&str, &str), Error> {
+        match self {
+            Range::Absolute(from, to) => Ok((from, to)),
+            Range::Keyword(_) => Err(UnsupportedError("--keyword", "Elasticsearch").into()),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Start of the search range [default: 2 minutes ago, or the configured search-from]
+    #[structopt(long = "search-from", short = "@")]
+    from: Option<String>,
+
+    /// End of the search range [default: now, or the configured search-to]
+    #[structopt(long = "search-to", short = "#")]
+    to: Option<String>,
+
+    /// Fractional second precision of the search range (millis, micros or nanos)
+    #[structopt(long, default_value = "millis")]
+    precision: Precision,
+
+    /// Swap an inverted search range instead of failing
+    #[structopt(long = "fix-range")]
+    fix_range: bool,
+
+    /// Widen the search range by starting this much earlier (e.g. 5m)
+    #[structopt(long = "extend-before", conflicts_with = "keyword")]
+    extend_before: Option<Span>,
+
+    /// Widen the search range by ending this much later (e.g. 5m)
+    #[structopt(long = "extend-after", conflicts_with = "keyword")]
+    extend_after: Option<Span>,
+
+    /// Let Graylog interpret --search-from as keyword range (e.g. "yesterday")
+    #[structopt(long, conflicts_with = "fix_range")]
+    keyword: bool,
+
+    #[structopt(flatten)]
+    output: output::Args,
+
+    #[structopt(flatten)]
+    elastic: elastic::Args,
+
+    #[structopt(flatten)]
+    graylog: graylog::Args,
+
+    /// Print a chart of message counts per interval (e.g. 1m) instead of messages
+    #[structopt(long)]
+    timechart: Option<String>,
+
+    /// Probe one record and report template fields it lacks instead of querying
+    #[structopt(long = "fields-from-template", conflicts_with = "timechart")]
+    fields_from_template: bool,
+
+    /// Print the number of matching messages instead of the messages
+    #[structopt(
+        long,
+        raw(conflicts_with_all = r#"&["timechart", "fields_from_template"]"#)
+    )]
+    count: bool,
+
+    /// Exit with code 2 when no records were output, e.g. for alerting scripts
+    #[structopt(
+        long = "fail-on-empty",
+        raw(conflicts_with_all = r#"&["timechart", "fields_from_template", "count"]"#)
+    )]
+    fail_on_empty: bool,
+
+    /// Additional query to run over the same time range (repeatable)
+    #[structopt(long, raw(number_of_values = "1"))]
+    multi: Vec<String>,
+
+    /// Stop querying after this long (e.g. 30s), keeping what was output so far
+    #[structopt(long = "timeout-total")]
+    timeout_total: Option<Span>,
+
+    /// Milliseconds to wait between requesting pages of large Graylog and Elasticsearch results
+    #[structopt(long = "page-delay", default_value = "0")]
+    page_delay: u64,
+
+    /// Stop after this many records have been output [default: the configured
+    /// max-records, if any]
+    #[structopt(long = "max-records")]
+    max_records: Option<u64>,
+
+    /// Prometheus Pushgateway URL to push record count, duration and errors to
+    #[structopt(long = "metrics-endpoint")]
+    metrics_endpoint: Option<String>,
+
+    /// Fail on an empty query instead of matching all messages
+    #[structopt(long = "no-default-query")]
+    no_default_query: bool,
+
+    /// Ignore the node's base query
+    #[structopt(long = "no-base-query")]
+    no_base_query: bool,
+
+    /// Add the query and node name to each record as _query and _node
+    #[structopt(long = "annotate-query")]
+    annotate_query: bool,
+
+    /// Run unrestricted queries over large search ranges without asking
+    #[structopt(long, short = "y")]
+    yes: bool,
+
+    #[structopt(name = "QUERY")]
+    query: Vec<String>,
+}
+
+impl Args {
+    fn from(&self) -> &str {
+        self.from
+            .as_ref()
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_FROM)
+    }
+
+    fn to(&self) -> &str {
+        self.to.as_ref().map(String::as_str).unwrap_or(DEFAULT_TO)
+    }
+
+    /// The search range, widened by --extend-before and --extend-after
+    fn range(&self) -> Result<Range, Error> {
+        if self.keyword {
+            return Ok(Range::Keyword(self.from().to_owned()));
+        }
+
+        let (mut from, mut to) =
+            datetime::parse_range(self.from(), self.to(), self.precision, self.fix_range)?;
+
+        if let Some(Span(before)) = self.extend_before {
+            from = datetime::shift(&from, -chrono::Duration::from_std(before)?, self.precision)?;
+        }
+        if let Some(Span(after)) = self.extend_after {
+            to = datetime::shift(&to, chrono::Duration::from_std(after)?, self.precision)?;
+        }
+
+        Ok(Range::Absolute(from, to))
+    }
+
+    /// All queries to run, scoped by `base` and labelled for output when
+    /// there is more than one
+    fn queries(&self, base: Option<&str>) -> Vec<(String, Vec<String>)> {
+        let label = |query: &[String]| match query.join(" ") {
+            ref s if s.is_empty() => String::from("*"),
+            s => s,
+        };
+
+        let mut queries = vec![(label(&self.query), scope(base, &self.query))];
+        for query in self.multi.iter() {
+            let query = std::slice::from_ref(query);
+            queries.push((label(query), scope(base, query)));
+        }
+
+        queries
+    }
+
+    /// Fields naming the query and node for --annotate-query
+    fn annotation(&self, node_name: &str, query: &[String]) -> Option<Record> {
+        if !self.annotate_query {
+            return None;
+        }
+
+        let mut annotation = Record::new();
+        let query = match query.join(" ") {
+            ref s if s.is_empty() => String::from("*"),
+            s => s,
+        };
+        annotation.insert(String::from("_query"), Value::from(query));
+        annotation.insert(String::from("_node"), Value::from(node_name));
+        Some(annotation)
+    }
+}
+
+/// Client and parameters for a Graylog search over the requested range
+fn graylog_search<'a>(
+    node: &GraylogNode,
+    node_name: &str,
+    range: &Range,
+    args: &'a Args,
+    options: &RequestOptions,
+) -> Result<(Client, HashMap<&'a str, String>), Error> {
+    let (client, mut params) = match range {
+        Range::Keyword(keyword) => {
+            let client = graylog::keyword_client(node, node_name, options)?;
+            (client, hashmap! { "keyword" => keyword.clone() })
+        }
+        Range::Absolute(from, to) => {
+            let client = graylog::node_client(node, node_name, options)?;
+            (
+                client,
+                hashmap! { "from" => from.clone(), "to" => to.clone() },
+            )
+        }
+    };
+
+    params.insert("limit", "0".into());
+    params.extend(args.graylog.params());
+
+    Ok((client, params))
+}
 
 fn query_graylog(
     node: &GraylogNode,
     node_name: &str,
-    handlebars: &Handlebars,
-    from: &str,
-    to: &str,
-    query: &[String],
+    base: Option<&str>,
+    range: &Range,
+    output: &mut Output,
+    args: &Args,
+    options: &RequestOptions,
 ) -> Result<(), Error> {
-    let client = graylog::node_client(node, node_name)?;
+    let (client, search) = graylog_search(node, node_name, range, args, options)?;
+    let queries = args.queries(base);
 
-    let from = datetime::parse_timestamp(&from)?.0;
-    let to = datetime::parse_timestamp(&to)?.1;
+    for (label, query) in queries.iter() {
+        let mut params = search.clone();
+        graylog::assign_query(query, &mut params);
 
-    let mut params = HashMap::new();
-    graylog::assign_query(&query, &mut params);
-
-    params.insert("limit", "0".into());
-    params.insert("from", from);
-    params.insert("to", to);
+        if queries.len() > 1 {
+            output.header(label)?;
+        }
 
-    graylog::run(&client, &params, &handlebars)?;
+        if args.count {
+            output.value(&graylog::count(&client, &params)?.to_string())?;
+        } else {
+            let annotation = args.annotation(node_name, query);
+            graylog::run(
+                &client,
+                &params,
+                args.graylog.common_fields,
+                args.graylog.highlighted(&args.output)?,
+                annotation.as_ref(),
+                output,
+            )?;
+        }
+    }
 
     Ok(())
 }
@@ -52,26 +311,204 @@ fn query_graylog(
 fn query_elastic(
     node: &ElasticNode,
     node_name: &str,
+    base: Option<&str>,
+    range: &Range,
+    output: &mut Output,
+    args: &Args,
+    options: &RequestOptions,
+) -> Result<(), Error> {
+    let (from, to) = range.absolute()?;
+
+    let mut requests: Vec<(String, elastic::Request)> = args
+        .queries(base)
+        .into_iter()
+        .map(|(label, query)| {
+            let mut request = elastic_request(args, &query, from, to);
+            request.annotation = args.annotation(node_name, &query);
+            (label, request)
+        })
+        .collect();
+
+    if let Some(ref interval) = args.timechart {
+        let client = elastic::node_client(
+            node,
+            node_name,
+            args.elastic.index.as_ref().map(String::as_str),
+            options,
+        )?;
+        let (_, request) = requests.remove(0);
+        elastic::timechart(&client, request, "@timestamp", interval, (from, to), output)
+    } else if args.count {
+        let client = elastic::count_client(
+            node,
+            node_name,
+            args.elastic.index.as_ref().map(String::as_str),
+            options,
+        )?;
+        for (label, request) in requests.iter() {
+            if requests.len() > 1 {
+                output.header(label)?;
+            }
+            output.value(&elastic::count(&client, request)?.to_string())?;
+        }
+        Ok(())
+    } else if requests.len() > 1 {
+        let client = elastic::multi_client(
+            node,
+            node_name,
+            args.elastic.index.as_ref().map(String::as_str),
+            options,
+        )?;
+        elastic::run_multi(&client, &requests, output)
+    } else {
+        let client = elastic::node_client(
+            node,
+            node_name,
+            args.elastic.index.as_ref().map(String::as_str),
+            options,
+        )?;
+        let (_, request) = requests.remove(0);
+        elastic::run(&client, &request, output)
+    }
+}
+
+/// Fetches a single record and reports which fields referenced by the
+/// template it lacks and which of its fields the template ignores.
+fn probe_fields(
+    node: &Node,
+    node_name: &str,
+    base: Option<&str>,
+    range: &Range,
     handlebars: &Handlebars,
-    from: &str,
-    to: &str,
-    query: &[String],
+    args: &Args,
+    options: &RequestOptions,
 ) -> Result<(), Error> {
-    let client = elastic::node_client(node, &node_name)?;
+    let (_, query) = args.queries(base).remove(0);
 
-    let from = datetime::parse_timestamp(&from)?.0;
-    let to = datetime::parse_timestamp(&to)?.1;
+    let records = match node {
+        Node::Graylog(node) => {
+            let (client, mut params) = graylog_search(node, node_name, range, args, options)?;
+            graylog::assign_query(&query, &mut params);
+            params.insert("limit", "1".into());
+            graylog::fetch(&client, &params)?
+        }
+        Node::Elastic(node) => {
+            let (from, to) = range.absolute()?;
+            let mut request = elastic_request(args, &query, from, to);
+            request.size = Some(1);
+            elastic::fetch(
+                &elastic::node_client(
+                    node,
+                    node_name,
+                    args.elastic.index.as_ref().map(String::as_str),
+                    options,
+                )?,
+                &request,
+            )?
+        }
+    };
 
-    let range = elastic::Query::Range(hashmap! {
-        "@timestamp".to_owned() => elastic::Range {
-            gte: Some(from),
-            lt: Some(to),
-            ..Default::default()
+    let record = records.into_iter().next().ok_or(NoProbeRecordError)?;
+    let fields = template::fields(handlebars);
+
+    let missing: Vec<&str> = fields
+        .iter()
+        .filter(|field| severity::lookup(&record, field).is_none())
+        .map(String::as_str)
+        .collect();
+
+    let unused: Vec<&str> = record
+        .keys()
+        .filter(|key| {
+            !fields
+                .iter()
+                .any(|field| field == *key || field.starts_with(&format!("{}.", key)))
+        })
+        .map(String::as_str)
+        .collect();
+
+    if missing.is_empty() {
+        println!("All {} template fields are present.", fields.len());
+    } else {
+        println!(
+            "Template fields missing from the record: {}",
+            missing.join(", ")
+        );
+    }
+
+    if !unused.is_empty() {
+        println!(
+            "Record fields unused by the template: {}",
+            unused.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Node and search range for --format summary
+fn summary_source(node: &Node, node_name: &str, range: &Range) -> String {
+    let kind = match node {
+        Node::Graylog(_) => "graylog",
+        Node::Elastic(_) => "elastic",
+    };
+
+    match range {
+        Range::Keyword(keyword) => format!("{} ({}) for {}", node_name, kind, keyword),
+        Range::Absolute(from, to) => {
+            format!("{} ({}) between {} and {}", node_name, kind, from, to)
         }
+    }
+}
+
+/// Asks on a terminal before matching everything over more than
+/// `BROAD_RANGE_HOURS`, unless --yes is given.
+fn confirm(base: Option<&str>, range: &Range, args: &Args) -> Result<(), Error> {
+    let (from, to) = match range {
+        Range::Absolute(from, to) => (from, to),
+        Range::Keyword(_) => return Ok(()),
+    };
+
+    if args.yes || args.count || !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stderr) {
+        return Ok(());
+    }
+
+    let broad = args
+        .queries(base)
+        .into_iter()
+        .find(|(_, query)| query.is_empty() || query.iter().all(|term| term.trim() == "*"));
+    let label = match broad {
+        Some((label, _)) => label,
+        None => return Ok(()),
+    };
+
+    let span = DateTime::parse_from_rfc3339(to)? - DateTime::parse_from_rfc3339(from)?;
+    if span < chrono::Duration::hours(BROAD_RANGE_HOURS) {
+        return Ok(());
+    }
+
+    let proceed = Confirmation::new()
+        .with_text(&format!(
+            "Query {} matches everything from {} to {}; proceed?",
+            label, from, to
+        ))
+        .default(false)
+        .interact()?;
+
+    if proceed {
+        Ok(())
+    } else {
+        Err(NotConfirmedError.into())
+    }
+}
+
+fn elastic_request(args: &Args, query: &[String], from: &str, to: &str) -> elastic::Request {
+    let range = elastic::Query::Range(hashmap! {
+        "@timestamp".to_owned() => elastic::Range::between(from.to_owned(), to.to_owned(), args.precision)
     });
 
-    let request = elastic::Request {
-        size: Some(10000),
+    elastic::Request {
+        size: None,
         sort: hashmap! {
             "@timestamp".to_owned() => "asc".to_owned()
         },
@@ -88,32 +525,133 @@ fn query_elastic(
         } else {
             range
         },
-    };
-
-    elastic::run(&client, &request, &handlebars)?;
-    Ok(())
+        source: args.elastic.source(),
+        script_fields: args.elastic.script_fields(),
+        stored_fields: args.elastic.stored_fields(),
+        docvalue_fields: args.elastic.docvalue_fields(),
+        aggs: None,
+        track_total_hits: args.elastic.track_total_hits,
+        terminate_after: args.elastic.terminate_after,
+        min_score: args.elastic.min_score,
+        collapse: args.elastic.collapse(),
+        hit_meta: args.elastic.hit_meta,
+        annotation: None,
+    }
 }
 
 pub fn run(
     config: Result<Config, Error>,
     node_name: String,
     template: String,
-    from: String,
-    to: String,
-    query: Vec<String>,
+    options: &RequestOptions,
+    mut args: Args,
 ) -> Result<(), Error> {
-    let (node, template) = match config {
-        Ok(ref config) => (
-            config::node(config, &node_name)?,
-            config::template(config, &template)?,
-        ),
+    let (node, template, variants, script) = match config {
+        Ok(ref config) => {
+            let node = config::node(config, &node_name)?;
+            let defaults = config::defaults(config, node);
+            args.from = args.from.or(defaults.search_from);
+            args.to = args.to.or(defaults.search_to);
+            args.max_records = args.max_records.or(defaults.max_records);
+
+            (
+                node,
+                config::template(config, &template)?,
+                args.output.variants(config)?,
+                config.script.as_ref(),
+            )
+        }
         Err(e) => return Err(e),
     };
 
-    let handlebars = template::compile(&template)?;
+    if args.no_default_query && args.query.is_empty() {
+        return Err(EmptyQueryError.into());
+    }
+
+    let handlebars = template::compile(&template, script)?;
+    let base = node.base_query().filter(|_| !args.no_base_query);
 
     match node {
-        Node::Graylog(node) => query_graylog(node, &node_name, &handlebars, &from, &to, &query),
-        Node::Elastic(node) => query_elastic(node, &node_name, &handlebars, &from, &to, &query),
+        Node::Graylog(_) if args.timechart.is_some() => {
+            return Err(UnsupportedError("--timechart", "Graylog").into())
+        }
+        Node::Elastic(_) if args.keyword => {
+            return Err(UnsupportedError("--keyword", "Elasticsearch").into())
+        }
+        _ => query::check_options(node, &args.elastic, &args.graylog)?,
+    }
+
+    let range = args.range()?;
+
+    if args.fields_from_template {
+        return probe_fields(node, &node_name, base, &range, &handlebars, &args, options);
+    }
+
+    confirm(base, &range, &args)?;
+
+    let source = summary_source(node, &node_name, &range);
+    let mut output = Output::new(&args.output, handlebars)?;
+    output.set_variants(variants);
+    output.set_severity_map(node.severity_map().clone());
+    if let Some(max) = args.max_records {
+        output.limit(max);
     }
+    let started = Instant::now();
+
+    let options = RequestOptions {
+        deadline: args
+            .timeout_total
+            .as_ref()
+            .map(|Span(budget)| started + *budget),
+        page_delay: Some(Duration::from_millis(args.page_delay)),
+        ..options.clone()
+    };
+
+    let result = match node {
+        Node::Graylog(node) => {
+            query_graylog(node, &node_name, base, &range, &mut output, &args, &options)
+        }
+        Node::Elastic(node) => {
+            query_elastic(node, &node_name, base, &range, &mut output, &args, &options)
+        }
+    };
+
+    let result = match result {
+        Err(e) => match e.downcast::<ResponseError>() {
+            Ok(ResponseError::DeadlineExceeded) => {
+                eprintln!("Stopped at --timeout-total; output is truncated");
+                Ok(())
+            }
+            Ok(e) => Err(e.into()),
+            Err(e) => Err(e),
+        },
+        result => result,
+    }
+    .and_then(|_| {
+        if args.count || args.timechart.is_some() {
+            return Ok(());
+        }
+        output.summary(&source, started.elapsed())
+    });
+
+    if let Some(ref endpoint) = args.metrics_endpoint {
+        let metrics = Metrics {
+            node: &node_name,
+            records: output.printed(),
+            duration: started.elapsed(),
+            errors: if result.is_err() { 1 } else { 0 },
+        };
+
+        if let Err(e) = metrics::push(endpoint, &metrics) {
+            eprintln!("Could not push metrics: {}", e);
+        }
+    }
+
+    if result.is_ok() && args.fail_on_empty && output.printed() == 0 {
+        // Exiting skips destructors, so the output has to be closed first
+        drop(output);
+        process::exit(EMPTY_EXIT_CODE);
+    }
+
+    result.and_then(|_| output.finish())
 }
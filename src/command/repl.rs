@@ -0,0 +1,366 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::config::{Config, Node};
+use crate::datetime;
+use crate::datetime::Precision;
+use crate::output;
+use crate::output::Output;
+use crate::query::{elastic, graylog, scope, Client, RequestOptions};
+use crate::template;
+use failure::{Error, Fail};
+use maplit::hashmap;
+use std::io;
+use std::io::{BufRead, Write};
+use structopt::StructOpt;
+
+const HELP: &str = "\
+Type a query to run it, or one of these commands:
+  :node NAME        switch to another node
+  :template NAME    switch to another template
+  :from TIME        set the start of the search range
+  :to TIME          set the end of the search range
+  :show             print the current node, template and search range
+  :help             print this help
+  :quit             leave the session (or press Ctrl-D)";
+
+#[derive(Debug, Fail)]
+#[fail(display = "Unknown command :{}; type :help for the list", _0)]
+pub struct UnknownCommandError(String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "Command :{} needs an argument", _0)]
+pub struct MissingArgumentError(String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "--tui is not supported by the repl, which prints each result right away")]
+pub struct TuiError;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Initial start of the search range
+    #[structopt(long = "search-from", short = "@", default_value = "2 minutes ago")]
+    from: String,
+
+    /// Initial end of the search range
+    #[structopt(long = "search-to", short = "#", default_value = "now")]
+    to: String,
+
+    /// Fractional second precision of the search range (millis, micros or nanos)
+    #[structopt(long, default_value = "millis")]
+    precision: Precision,
+
+    #[structopt(flatten)]
+    output: output::Args,
+}
+
+/// What a line typed at the prompt asks for
+#[derive(Debug, PartialEq)]
+enum Input<'a> {
+    Query(&'a str),
+    Node(&'a str),
+    Template(&'a str),
+    From(&'a str),
+    To(&'a str),
+    Show,
+    Help,
+    Quit,
+    Nothing,
+}
+
+fn parse(line: &str) -> Result<Input<'_>, Error> {
+    let line = line.trim();
+
+    let command = if line.starts_with(':') {
+        &line[1..]
+    } else if line.is_empty() {
+        return Ok(Input::Nothing);
+    } else {
+        return Ok(Input::Query(line));
+    };
+
+    let (name, argument) = match command.find(char::is_whitespace) {
+        Some(i) => (&command[..i], command[i..].trim()),
+        None => (command, ""),
+    };
+    let argument = || {
+        Some(argument)
+            .filter(|argument| !argument.is_empty())
+            .ok_or_else(|| MissingArgumentError(name.to_owned()))
+    };
+
+    Ok(match name {
+        "node" => Input::Node(argument()?),
+        "template" => Input::Template(argument()?),
+        "from" => Input::From(argument()?),
+        "to" => Input::To(argument()?),
+        "show" => Input::Show,
+        "help" => Input::Help,
+        "quit" | "q" | "exit" => Input::Quit,
+        _ => return Err(UnknownCommandError(name.to_owned()).into()),
+    })
+}
+
+/// An authenticated client for `node`, reused for all queries against it
+fn client(node: &Node, node_name: &str, options: &RequestOptions) -> Result<Client, Error> {
+    match node {
+        Node::Graylog(node) => graylog::node_client(node, node_name, options),
+        Node::Elastic(node) => elastic::node_client(node, node_name, None, options),
+    }
+}
+
+fn elastic_request(
+    query: &[String],
+    from: &str,
+    to: &str,
+    precision: Precision,
+) -> elastic::Request {
+    let range = elastic::Query::Range(hashmap! {
+        "@timestamp".to_owned() => elastic::Range::between(from.to_owned(), to.to_owned(), precision)
+    });
+
+    elastic::Request {
+        size: None,
+        sort: hashmap! {
+            "@timestamp".to_owned() => "asc".to_owned()
+        },
+        query: if !query.is_empty() {
+            elastic::Query::Bool(elastic::QueryBool {
+                must: Some(vec![
+                    Box::new(elastic::Query::QueryString {
+                        query: query.join(" "),
+                    }),
+                    Box::new(range),
+                ]),
+                ..Default::default()
+            })
+        } else {
+            range
+        },
+        source: None,
+        script_fields: None,
+        stored_fields: None,
+        docvalue_fields: None,
+        aggs: None,
+        track_total_hits: None,
+        terminate_after: None,
+        min_score: None,
+        collapse: None,
+        hit_meta: false,
+        annotation: None,
+    }
+}
+
+/// Node, template and search range kept across the queries of a session
+struct Session<'a> {
+    config: &'a Config,
+    args: &'a Args,
+    options: &'a RequestOptions,
+    node_name: String,
+    node: &'a Node,
+    client: Client,
+    template: String,
+    output: Output,
+    from: String,
+    to: String,
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        config: &'a Config,
+        node_name: String,
+        template: String,
+        args: &'a Args,
+        options: &'a RequestOptions,
+    ) -> Result<Self, Error> {
+        let node = config::node(config, &node_name)?;
+
+        Ok(Self {
+            config,
+            args,
+            options,
+            client: client(node, &node_name, options)?,
+            output: Self::output(config, node, &template, args)?,
+            node_name,
+            node,
+            template,
+            from: args.from.clone(),
+            to: args.to.clone(),
+        })
+    }
+
+    fn output(config: &Config, node: &Node, template: &str, args: &Args) -> Result<Output, Error> {
+        let mut output = Output::new(
+            &args.output,
+            template::compile(config::template(config, template)?, config.script.as_ref())?,
+        )?;
+        output.set_variants(args.output.variants(config)?);
+        output.set_severity_map(node.severity_map().clone());
+        Ok(output)
+    }
+
+    fn prompt(&self) -> String {
+        format!("50shades:{}> ", self.node_name)
+    }
+
+    fn switch_node(&mut self, name: &str) -> Result<(), Error> {
+        let node = config::node(self.config, name)?;
+        self.client = client(node, name, self.options)?;
+        self.output.set_severity_map(node.severity_map().clone());
+        self.node = node;
+        self.node_name = name.to_owned();
+        Ok(())
+    }
+
+    fn switch_template(&mut self, name: &str) -> Result<(), Error> {
+        self.output = Self::output(self.config, self.node, name, self.args)?;
+        self.template = name.to_owned();
+        Ok(())
+    }
+
+    /// Checks and sets the search range, keeping the previous one if invalid.
+    fn set_range(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        datetime::parse_range(from, to, self.args.precision, false)?;
+        self.from = from.to_owned();
+        self.to = to.to_owned();
+        Ok(())
+    }
+
+    fn show(&self) {
+        eprintln!("Node:     {}", self.node_name);
+        eprintln!("Template: {}", self.template);
+        eprintln!("Range:    {} to {}", self.from, self.to);
+    }
+
+    fn query(&mut self, query: &str) -> Result<(), Error> {
+        // Relative times like "2 minutes ago" are resolved anew for each query
+        let (from, to) = datetime::parse_range(&self.from, &self.to, self.args.precision, false)?;
+        let query = scope(self.node.base_query(), &[query.to_owned()]);
+
+        match self.node {
+            Node::Graylog(_) => {
+                let mut params = hashmap! {
+                    "from" => from,
+                    "to" => to,
+                    "limit" => String::from("0"),
+                };
+                graylog::assign_query(&query, &mut params);
+                graylog::run(&self.client, &params, false, false, None, &mut self.output)?;
+            }
+            Node::Elastic(_) => {
+                let request = elastic_request(&query, &from, &to, self.args.precision);
+                elastic::run(&self.client, &request, &mut self.output)?;
+            }
+        }
+
+        self.output.finish()?;
+        self.output.flush()
+    }
+
+    /// Acts on one line of input, returning whether the session goes on.
+    fn handle(&mut self, line: &str) -> Result<bool, Error> {
+        match parse(line)? {
+            Input::Query(query) => self.query(query)?,
+            Input::Node(name) => self.switch_node(name)?,
+            Input::Template(name) => self.switch_template(name)?,
+            Input::From(from) => {
+                let to = self.to.clone();
+                self.set_range(from, &to)?
+            }
+            Input::To(to) => {
+                let from = self.from.clone();
+                self.set_range(&from, to)?
+            }
+            Input::Show => self.show(),
+            Input::Help => eprintln!("{}", HELP),
+            Input::Quit => return Ok(false),
+            Input::Nothing => (),
+        }
+
+        Ok(true)
+    }
+}
+
+pub fn run(
+    config: Result<Config, Error>,
+    node_name: String,
+    template: String,
+    options: &RequestOptions,
+    args: Args,
+) -> Result<(), Error> {
+    let config = config?;
+    if args.output.tui {
+        return Err(TuiError.into());
+    }
+    datetime::parse_range(&args.from, &args.to, args.precision, false)?;
+    let mut session = Session::new(&config, node_name, template, &args, options)?;
+
+    let interactive = atty::is(atty::Stream::Stdin);
+    if interactive {
+        eprintln!("Type :help for the available commands.");
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        if interactive {
+            eprint!("{}", session.prompt());
+            io::stderr().flush()?;
+        }
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => {
+                if interactive {
+                    eprintln!();
+                }
+                break;
+            }
+        };
+
+        match session.handle(&line) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Input};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("  ").unwrap(), Input::Nothing);
+        assert_eq!(
+            parse(" level:3 AND source:web ").unwrap(),
+            Input::Query("level:3 AND source:web")
+        );
+        assert_eq!(parse(":node prod").unwrap(), Input::Node("prod"));
+        assert_eq!(
+            parse(":from  1 hour ago").unwrap(),
+            Input::From("1 hour ago")
+        );
+        assert_eq!(parse(":q").unwrap(), Input::Quit);
+        assert!(parse(":node").is_err());
+        assert!(parse(":nodes prod").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::config::Config;
+use crate::output::Record;
+use crate::remote;
+use crate::template;
+use failure::Error;
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Renders a sample record to check that a template works
+    #[structopt(name = "check")]
+    Check(CheckArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CheckArgs {
+    /// Template file or HTTP(S) URL to check instead of the configured template
+    #[structopt(long = "template-file")]
+    template_file: Option<String>,
+
+    /// JSON file holding a sample record
+    #[structopt(long, parse(from_os_str))]
+    sample: PathBuf,
+}
+
+fn check(config: Result<Config, Error>, template: String, args: CheckArgs) -> Result<(), Error> {
+    let script = config
+        .as_ref()
+        .ok()
+        .and_then(|config| config.script.clone());
+    let template = match args.template_file {
+        Some(ref url) if remote::is_url(url) => {
+            remote::fetch(url)?.trim_end_matches('\n').to_owned()
+        }
+        Some(path) => fs::read_to_string(path)?.trim_end_matches('\n').to_owned(),
+        None => config::template(&config?, &template)?.to_owned(),
+    };
+
+    let sample: Record = serde_json::from_str(&fs::read_to_string(&args.sample)?)?;
+    let line = template::render(&template::compile(&template, script.as_ref())?, &sample)?;
+
+    println!("{}", line);
+    eprintln!("Template rendered successfully.");
+
+    Ok(())
+}
+
+pub fn run(config: Result<Config, Error>, template: String, command: Command) -> Result<(), Error> {
+    match command {
+        Command::Check(args) => check(config, template, args),
+    }
+}
@@ -0,0 +1,180 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::config::{Config, Node};
+use crate::datetime;
+use crate::datetime::Precision;
+use crate::output;
+use crate::output::{Output, Record};
+use crate::query::{elastic, graylog, scope, RequestOptions};
+use crate::severity::SeverityMap;
+use crate::template;
+use chrono::prelude::*;
+use failure::Error;
+use maplit::hashmap;
+use serde_json::Value;
+use structopt::StructOpt;
+
+/// Fields consulted for a record's timestamp when merging the timeline
+const TIMESTAMP_FIELDS: &[&str] = &["@timestamp", "timestamp"];
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    #[structopt(long = "search-from", short = "@", default_value = "1 day ago")]
+    from: String,
+
+    #[structopt(long = "search-to", short = "#", default_value = "now")]
+    to: String,
+
+    /// Fractional second precision of the search range (millis, micros or nanos)
+    #[structopt(long, default_value = "millis")]
+    precision: Precision,
+
+    /// Comma-separated nodes to search (defaults to --node)
+    #[structopt(long, raw(use_delimiter = "true"))]
+    nodes: Vec<String>,
+
+    #[structopt(flatten)]
+    output: output::Args,
+
+    #[structopt(name = "TRACE-ID")]
+    id: String,
+}
+
+fn timestamp(record: &Record) -> Option<DateTime<Utc>> {
+    TIMESTAMP_FIELDS
+        .iter()
+        .filter_map(|field| record.get(*field).and_then(Value::as_str))
+        .find_map(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|datetime| datetime.with_timezone(&Utc))
+}
+
+/// Records of `node` whose trace field holds `id`, tagged with the node as `_node`
+fn search(
+    node: &Node,
+    node_name: &str,
+    (from, to): (&str, &str),
+    args: &Args,
+    options: &RequestOptions,
+) -> Result<Vec<Record>, Error> {
+    let query = scope(
+        node.base_query(),
+        &[format!("{}:\"{}\"", node.trace_field(), args.id)],
+    );
+
+    let records = match node {
+        Node::Graylog(node) => {
+            let client = graylog::node_client(node, node_name, options)?;
+            let mut params = hashmap! {
+                "from" => from.to_owned(),
+                "to" => to.to_owned(),
+                "limit" => String::from("0"),
+            };
+            graylog::assign_query(&query, &mut params);
+            graylog::fetch(&client, &params)?
+        }
+        Node::Elastic(node) => {
+            let client = elastic::node_client(node, node_name, None, options)?;
+            let request = elastic::Request {
+                size: None,
+                sort: hashmap! {
+                    "@timestamp".to_owned() => "asc".to_owned()
+                },
+                query: elastic::Query::Bool(elastic::QueryBool {
+                    must: Some(vec![
+                        Box::new(elastic::Query::QueryString {
+                            query: query.join(" "),
+                        }),
+                        Box::new(elastic::Query::Range(hashmap! {
+                            "@timestamp".to_owned() => elastic::Range::between(
+                                from.to_owned(),
+                                to.to_owned(),
+                                args.precision,
+                            )
+                        })),
+                    ]),
+                    ..Default::default()
+                }),
+                source: None,
+                script_fields: None,
+                stored_fields: None,
+                docvalue_fields: None,
+                aggs: None,
+                track_total_hits: None,
+                terminate_after: None,
+                min_score: None,
+                collapse: None,
+                hit_meta: false,
+                annotation: None,
+            };
+            elastic::fetch(&client, &request)?
+        }
+    };
+
+    Ok(records
+        .into_iter()
+        .map(|mut record| {
+            record.insert(String::from("_node"), Value::from(node_name));
+            record
+        })
+        .collect())
+}
+
+pub fn run(
+    config: Result<Config, Error>,
+    node_name: String,
+    template: String,
+    options: &RequestOptions,
+    args: Args,
+) -> Result<(), Error> {
+    let config = config?;
+    let nodes = if args.nodes.is_empty() {
+        vec![node_name]
+    } else {
+        args.nodes.clone()
+    };
+
+    let mut output = Output::new(
+        &args.output,
+        template::compile(
+            config::template(&config, &template)?,
+            config.script.as_ref(),
+        )?,
+    )?;
+    output.set_variants(args.output.variants(&config)?);
+
+    let (from, to) = datetime::parse_range(&args.from, &args.to, args.precision, false)?;
+
+    // Records of all nodes share one output, so their severity maps are merged
+    let mut severity_map = SeverityMap::new();
+    let mut records = Vec::new();
+    for name in nodes.iter() {
+        let node = config::node(&config, name)?;
+        severity_map.extend(node.severity_map().clone());
+        records.extend(search(node, name, (&from, &to), &args, options)?);
+    }
+    output.set_severity_map(severity_map);
+
+    // Records without a timestamp go first, keeping their order
+    records.sort_by_key(timestamp);
+
+    for record in records.iter() {
+        output.print(record)?;
+    }
+
+    output.finish()
+}
@@ -14,20 +14,31 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::query::unix;
+use crate::remote;
+use crate::severity::SeverityMap;
+use crate::template;
+use crate::template::Script;
+use dialoguer::Select;
 use dirs;
 use failure::{Error, Fail};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::default::Default;
+use std::env;
 use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use std::ops::Deref;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use toml;
+use url::Url;
 
 const DEFAULT_TEMPLATE: &str = r#"[{{default container_name "-"}}] {{{message}}}"#;
+const LOCAL_GRAYLOG: &str = "http://localhost:9000";
+const LOCAL_ELASTIC: &str = "http://localhost:9200";
+const DEFAULT_TRACE_FIELD: &str = "trace_id";
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "kebab-case")]
@@ -36,16 +47,139 @@ pub enum Node {
     Elastic(ElasticNode),
 }
 
+impl Node {
+    pub fn url(&self) -> &str {
+        match self {
+            Node::Graylog(node) => &node.url,
+            Node::Elastic(node) => &node.url,
+        }
+    }
+
+    /// Points the node at the conventional localhost address for its type,
+    /// keeping the URL's path.
+    pub fn localize(&mut self) -> Result<(), Error> {
+        let (url, local) = match self {
+            Node::Graylog(node) => (&mut node.url, LOCAL_GRAYLOG),
+            Node::Elastic(node) => (&mut node.url, LOCAL_ELASTIC),
+        };
+
+        let parsed = Url::parse(url)?;
+        let mut localized = Url::parse(local)?;
+        localized.set_path(parsed.path());
+        // Setting credentials cannot fail on the http base URLs above
+        let _ = localized.set_username(parsed.username());
+        let _ = localized.set_password(parsed.password());
+        *url = localized.into_string();
+
+        Ok(())
+    }
+
+    /// Field the `trace` command searches for trace IDs, `trace_id` unless configured
+    pub fn trace_field(&self) -> &str {
+        let field = match self {
+            Node::Graylog(node) => node.trace_field.as_ref().map(String::as_str),
+            Node::Elastic(node) => node.trace_field.as_ref().map(String::as_str),
+        };
+
+        field.unwrap_or(DEFAULT_TRACE_FIELD)
+    }
+
+    pub fn base_query(&self) -> Option<&str> {
+        match self {
+            Node::Graylog(node) => node.base_query.as_ref().map(String::as_str),
+            Node::Elastic(node) => node.base_query.as_ref().map(String::as_str),
+        }
+    }
+
+    pub fn severity_map(&self) -> &SeverityMap {
+        match self {
+            Node::Graylog(node) => &node.severity_map,
+            Node::Elastic(node) => &node.severity_map,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GraylogNode {
     pub url: String,
     pub user: String,
+    /// Query ANDed with every search against this node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_query: Option<String>,
+    /// Field holding trace IDs, see `Node::trace_field`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_field: Option<String>,
+    /// Extra request headers; values may reference environment variables as ${NAME}
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Canonical severities for the level values of this node's sources
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub severity_map: SeverityMap,
+    /// Search and follow parameters used unless given on the command line
+    #[serde(default, skip_serializing_if = "Defaults::is_empty")]
+    pub defaults: Defaults,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ElasticNode {
     pub url: String,
     pub user: Option<String>,
+    /// Query ANDed with every search against this node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_query: Option<String>,
+    /// Field holding trace IDs, see `Node::trace_field`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_field: Option<String>,
+    /// Extra request headers; values may reference environment variables as ${NAME}
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Canonical severities for the level values of this node's sources
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub severity_map: SeverityMap,
+    /// Search and follow parameters used unless given on the command line
+    #[serde(default, skip_serializing_if = "Defaults::is_empty")]
+    pub defaults: Defaults,
+}
+
+/// Defaults for the `query` and `follow` options of the same names
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Defaults {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll: Option<u64>,
+    /// Also accepted as `limit`
+    #[serde(alias = "limit", skip_serializing_if = "Option::is_none")]
+    pub max_records: Option<u64>,
+}
+
+impl Defaults {
+    fn is_empty(&self) -> bool {
+        self.search_from.is_none()
+            && self.search_to.is_none()
+            && self.latency.is_none()
+            && self.poll.is_none()
+            && self.max_records.is_none()
+    }
+
+    /// These defaults, completed by `other` where unset
+    fn or(&self, other: &Defaults) -> Defaults {
+        Defaults {
+            search_from: self
+                .search_from
+                .clone()
+                .or_else(|| other.search_from.clone()),
+            search_to: self.search_to.clone().or_else(|| other.search_to.clone()),
+            latency: self.latency.or(other.latency),
+            poll: self.poll.or(other.poll),
+            max_records: self.max_records.or(other.max_records),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -69,9 +203,18 @@ impl Deref for Templates {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
+    /// Search and follow parameters for all nodes, see `defaults`
+    #[serde(default, skip_serializing_if = "Defaults::is_empty")]
+    pub defaults: Defaults,
     pub nodes: HashMap<String, Node>,
     #[serde(default)]
     pub templates: Templates,
+    /// Lua script whose functions become template helpers, see `helper_script`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub helpers: Option<String>,
+    /// The loaded `helpers` script, if any
+    #[serde(skip)]
+    pub script: Option<Script>,
 }
 
 #[derive(Debug, Fail)]
@@ -98,6 +241,83 @@ pub struct NoConfigError(pub String);
 #[fail(display = "Unsupported node type: {}", _0)]
 pub struct NodeTypeError(pub String);
 
+#[derive(Debug, Fail)]
+#[fail(display = "Invalid URL {} for node {}: {}", url, node, message)]
+pub struct InvalidUrlError {
+    node: String,
+    url: String,
+    message: String,
+}
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Environment variable {} referenced by the configuration is not set",
+    _0
+)]
+pub struct MissingVariableError(String);
+
+/// Replaces `${NAME}` references in `value` with the environment variable's value.
+pub fn interpolate(value: &str) -> Result<String, MissingVariableError> {
+    let mut result = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let name = &rest[start + 2..end];
+        result.push_str(&rest[..start]);
+        result.push_str(&env::var(name).map_err(|_| MissingVariableError(name.into()))?);
+        rest = &rest[end + 1..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Names of the environment variables `value` references as `${NAME}`.
+pub fn variables(value: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        names.push(&rest[start + 2..end]);
+        rest = &rest[end + 1..];
+    }
+
+    names
+}
+
+fn validate(config: &Config) -> Result<(), InvalidUrlError> {
+    for (name, node) in config.nodes.iter() {
+        let error = |message: String| InvalidUrlError {
+            node: name.clone(),
+            url: node.url().to_owned(),
+            message,
+        };
+
+        match Url::parse(node.url()) {
+            Ok(ref url) if url.cannot_be_a_base() => {
+                return Err(error(String::from("not a valid base URL")))
+            }
+            Ok(ref url) if url.scheme() == unix::SCHEME && unix::socket(url.as_str()).is_none() => {
+                return Err(error(String::from("no socket path given as the host")))
+            }
+            Ok(_) => (),
+            Err(e) => return Err(error(e.to_string())),
+        }
+    }
+
+    Ok(())
+}
+
 pub fn default() -> Result<String, Error> {
     Ok(dirs::config_dir()
         .and_then(|path| {
@@ -111,22 +331,60 @@ pub fn default() -> Result<String, Error> {
         .ok_or(ConfigPathError)?)
 }
 
-pub fn read(path: String) -> Result<Config, Error> {
-    let mut file = match File::open(path.clone()) {
+pub fn read_string(path: &str) -> Result<String, Error> {
+    let mut file = match File::open(path) {
         Ok(file) => file,
-        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(NoConfigError(path).into()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(NoConfigError(path.to_owned()).into())
+        }
         Err(e) => return Err(e.into()),
     };
 
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
+    Ok(contents)
+}
 
-    match toml::from_str(&contents) {
-        Ok(config) => Ok(config),
+pub fn parse(contents: &str) -> Result<Config, Error> {
+    match toml::from_str(contents) {
+        Ok(config) => {
+            validate(&config)?;
+            Ok(config)
+        }
         Err(e) => Err(ParseError(e.to_string()).into()),
     }
 }
 
+/// Location of the configured helper script, if any, taken relative to the
+/// directory of the configuration file at `path` unless that is a URL.
+pub fn helper_script(config: &Config, path: &str) -> Option<PathBuf> {
+    config.helpers.as_ref().map(|script| {
+        match Path::new(path).parent().filter(|_| !remote::is_url(path)) {
+            Some(dir) => dir.join(script),
+            None => PathBuf::from(script),
+        }
+    })
+}
+
+/// Loads the configured helper script, if any, into `config.script`.
+pub fn load_script(config: &mut Config, path: &str) -> Result<(), Error> {
+    if let Some(script) = helper_script(config, path) {
+        config.script = Some(template::load_script(&script)?);
+    }
+
+    Ok(())
+}
+
+/// Reads the configuration file at `path`, which may also be a URL to fetch,
+/// see `remote::fetch`.
+pub fn read(path: String) -> Result<Config, Error> {
+    if remote::is_url(&path) {
+        return parse(&remote::fetch(&path)?);
+    }
+
+    parse(&read_string(&path)?)
+}
+
 pub fn node<'a>(config: &'a Config, name: &str) -> Result<&'a Node, MissingNodeError> {
     Ok(config
         .nodes
@@ -134,6 +392,16 @@ pub fn node<'a>(config: &'a Config, name: &str) -> Result<&'a Node, MissingNodeE
         .ok_or_else(|| MissingNodeError(String::from(name)))?)
 }
 
+/// Defaults for `node`, falling back to the global ones.
+pub fn defaults(config: &Config, node: &Node) -> Defaults {
+    let defaults = match node {
+        Node::Graylog(node) => &node.defaults,
+        Node::Elastic(node) => &node.defaults,
+    };
+
+    defaults.or(&config.defaults)
+}
+
 pub fn template<'a>(config: &'a Config, name: &str) -> Result<&'a str, MissingTemplateError> {
     Ok(config
         .templates
@@ -141,6 +409,78 @@ pub fn template<'a>(config: &'a Config, name: &str) -> Result<&'a str, MissingTe
         .ok_or_else(|| MissingTemplateError(String::from(name)))?)
 }
 
+/// Levenshtein distance between `a` and `b`, for suggesting configured names.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Lets the user pick one of `names`, closest to the missing `name` first, if
+/// running in a terminal. Otherwise, or if the user declines, keeps `name`.
+fn pick<'a, I>(kind: &str, name: String, names: I) -> Result<String, Error>
+where
+    I: Iterator<Item = &'a String>,
+{
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stderr) {
+        return Ok(name);
+    }
+
+    let mut names: Vec<&String> = names.collect();
+    if names.is_empty() {
+        return Ok(name);
+    }
+    names.sort_by_key(|candidate| (distance(&name, candidate), candidate.as_str()));
+
+    let selection = Select::new()
+        .with_prompt(&format!(
+            "{} {} is not configured; pick one instead",
+            kind, name
+        ))
+        .default(0)
+        .items(&names)
+        .interact_opt()?;
+
+    Ok(match selection {
+        Some(n) => names[n].clone(),
+        None => name,
+    })
+}
+
+/// Resolves a node name missing from the configuration interactively, see `pick`.
+pub fn pick_node(config: &Config, name: String) -> Result<String, Error> {
+    if config.nodes.contains_key(&name) {
+        return Ok(name);
+    }
+
+    pick("Node", name, config.nodes.keys())
+}
+
+/// Resolves a template name missing from the configuration interactively, see `pick`.
+pub fn pick_template(config: &Config, name: String) -> Result<String, Error> {
+    if config.templates.contains_key(&name) {
+        return Ok(name);
+    }
+
+    pick("Template", name, config.templates.keys())
+}
+
 pub fn write(path: &str, config: &Config) -> Result<(), Error> {
     if let Some(parent) = Path::new(path).parent() {
         fs::create_dir_all(&parent)?;
@@ -150,3 +490,73 @@ pub fn write(path: &str, config: &Config) -> Result<(), Error> {
     file.write_all(toml::to_string_pretty(config)?.as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::{defaults, distance, interpolate, variables, Config};
+    use std::env;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("prd", "prod"), 1);
+        assert_eq!(distance("prod", "prod"), 0);
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(distance("staging", "prod"), 7);
+    }
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("FIFTYSHADES_TEST_TENANT", "acme");
+
+        assert_eq!(interpolate("plain").unwrap(), "plain");
+        assert_eq!(
+            interpolate("tenant-${FIFTYSHADES_TEST_TENANT}!").unwrap(),
+            "tenant-acme!"
+        );
+        assert_eq!(interpolate("${unterminated").unwrap(), "${unterminated");
+        assert!(interpolate("${FIFTYSHADES_TEST_UNSET}").is_err());
+        assert_eq!(variables("${A}-${B}-${unterminated"), vec!["A", "B"]);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [defaults]
+            search-from = "1 hour ago"
+            latency = 5
+
+            [nodes.prod]
+            type = "elastic"
+            url = "http://localhost:9200/"
+            trace_field = "request_id"
+            headers = { X-Tenant = "acme" }
+            defaults = { latency = 10, poll = 250 }
+
+            [nodes.dev]
+            type = "elastic"
+            url = "http://localhost:9200/"
+            defaults = { limit = 100 }
+
+            [templates]
+            default = "{{message}}"
+            "#,
+        )
+        .unwrap();
+
+        let defaults = defaults(&config, &config.nodes["prod"]);
+        assert_eq!(
+            defaults.search_from.as_ref().map(String::as_str),
+            Some("1 hour ago")
+        );
+        assert_eq!(defaults.latency, Some(10));
+        assert_eq!(defaults.poll, Some(250));
+        assert_eq!(defaults.max_records, None);
+        assert_eq!(
+            super::defaults(&config, &config.nodes["dev"]).max_records,
+            Some(100)
+        );
+
+        assert!(toml::to_string(&config).is_ok());
+    }
+}
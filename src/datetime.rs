@@ -17,6 +17,7 @@
 use chrono::prelude::*;
 use chrono::{Local, TimeZone, Utc};
 use failure::{Error, Fail};
+use std::str::FromStr;
 
 #[derive(Debug, Fail)]
 #[fail(display = "Could not interpret timestamp {}: {}", timestamp, message)]
@@ -29,21 +30,150 @@ pub struct DateParseError {
 #[fail(display = "Could not determine local timezone")]
 pub struct LocalTimeZoneError;
 
-fn convert_datetime(datetime: NaiveDateTime) -> Result<String, LocalTimeZoneError> {
+#[derive(Debug, Fail)]
+#[fail(display = "Unsupported timestamp precision: {}", _0)]
+pub struct PrecisionError(String);
+
+/// Fractional second precision of timestamps passed to nodes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl FromStr for Precision {
+    type Err = PrecisionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "millis" => Ok(Precision::Millis),
+            "micros" => Ok(Precision::Micros),
+            "nanos" => Ok(Precision::Nanos),
+            _ => Err(PrecisionError(s.into())),
+        }
+    }
+}
+
+impl From<Precision> for SecondsFormat {
+    fn from(precision: Precision) -> Self {
+        match precision {
+            Precision::Millis => SecondsFormat::Millis,
+            Precision::Micros => SecondsFormat::Micros,
+            Precision::Nanos => SecondsFormat::Nanos,
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Could not interpret duration {}; use e.g. 90s, 15m or 1h30m",
+    _0
+)]
+pub struct SpanError(String);
+
+/// Wall-clock duration given as a sequence of amounts with d, h, m or s units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span(pub std::time::Duration);
+
+impl FromStr for Span {
+    type Err = SpanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || SpanError(s.into());
+        let mut seconds: u64 = 0;
+        let mut amount = String::new();
+        let mut add = |amount: &str, unit: u64| {
+            seconds = amount
+                .parse::<u64>()
+                .ok()
+                .and_then(|amount| amount.checked_mul(unit))
+                .and_then(|amount| seconds.checked_add(amount))
+                .ok_or_else(error)?;
+            Ok(())
+        };
+
+        for c in s.trim().chars() {
+            if c.is_ascii_digit() {
+                amount.push(c);
+                continue;
+            }
+
+            let unit = match c {
+                'd' => 86400,
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => return Err(error()),
+            };
+            add(&amount, unit)?;
+            amount.clear();
+        }
+
+        if !amount.is_empty() {
+            add(&amount, 1)?;
+        } else if s.trim().is_empty() {
+            return Err(error());
+        }
+
+        // Spans are added to timestamps, which have to stay representable
+        let span = std::time::Duration::from_secs(seconds);
+        chrono::Duration::from_std(span).map_err(|_| error())?;
+        Ok(Span(span))
+    }
+}
+
+pub fn format(datetime: &DateTime<Utc>, precision: Precision) -> String {
+    datetime.to_rfc3339_opts(precision.into(), true)
+}
+
+/// The RFC 3339 `timestamp` moved by `by` at `precision`.
+pub fn shift(
+    timestamp: &str,
+    by: chrono::Duration,
+    precision: Precision,
+) -> Result<String, DateParseError> {
+    let datetime = DateTime::parse_from_rfc3339(timestamp).map_err(|e| DateParseError {
+        timestamp: timestamp.into(),
+        message: e.to_string(),
+    })?;
+
+    Ok(format(&(datetime.with_timezone(&Utc) + by), precision))
+}
+
+/// The first timestamp after the RFC 3339 `timestamp` at `precision`.
+pub fn after(timestamp: &str, precision: Precision) -> Result<String, DateParseError> {
+    let step = match precision {
+        Precision::Millis => chrono::Duration::milliseconds(1),
+        Precision::Micros => chrono::Duration::microseconds(1),
+        Precision::Nanos => chrono::Duration::nanoseconds(1),
+    };
+
+    shift(timestamp, step, precision)
+}
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Search range ends before it starts ({} > {}); swap the bounds or pass --fix-range",
+    from, to
+)]
+pub struct InvertedRangeError {
+    from: String,
+    to: String,
+}
+
+fn convert_datetime(datetime: NaiveDateTime) -> Result<DateTime<Utc>, LocalTimeZoneError> {
     match Local::now()
         .timezone()
         .from_local_datetime(&datetime)
         .single()
     {
         None => Err(LocalTimeZoneError),
-        Some(t) => Ok(t
-            .with_timezone(&Utc)
-            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
-            .to_string()),
+        Some(t) => Ok(t.with_timezone(&Utc)),
     }
 }
 
-pub fn parse_timestamp(timestamp: &str) -> Result<(String, String), Error> {
+fn parse(timestamp: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), Error> {
     match two_timer::parse(timestamp, None) {
         Ok((from, to, _)) => Ok((convert_datetime(from)?, convert_datetime(to)?)),
         Err(e) => Err(DateParseError {
@@ -53,3 +183,56 @@ pub fn parse_timestamp(timestamp: &str) -> Result<(String, String), Error> {
         .into()),
     }
 }
+
+pub fn parse_timestamp(timestamp: &str, precision: Precision) -> Result<(String, String), Error> {
+    let (from, to) = parse(timestamp)?;
+    Ok((format(&from, precision), format(&to, precision)))
+}
+
+/// Parses the start of `from` and the end of `to` into a search range. An
+/// inverted range is an error unless `fix` is set, in which case the bounds
+/// are swapped.
+pub fn parse_range(
+    from: &str,
+    to: &str,
+    precision: Precision,
+    fix: bool,
+) -> Result<(String, String), Error> {
+    let (start, end) = (parse(from)?.0, parse(to)?.1);
+
+    if start <= end {
+        return Ok((format(&start, precision), format(&end, precision)));
+    }
+
+    let (start, end) = (format(&start, precision), format(&end, precision));
+
+    if fix {
+        eprintln!("Search range inverted, swapping {} and {}", start, end);
+        Ok((end, start))
+    } else {
+        Err(InvertedRangeError {
+            from: start,
+            to: end,
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Span;
+    use std::time::Duration;
+
+    #[test]
+    fn test_span() {
+        let span = |s: &str| s.parse::<Span>().map(|span| span.0);
+
+        assert_eq!(span("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(span("90").unwrap(), Duration::from_secs(90));
+        assert!(span("").is_err());
+        assert!(span("1w").is_err());
+        assert!(span("999999999999999999d").is_err());
+        assert!(span("18446744073709551615s1s").is_err());
+        assert!(span("18446744073709551616s").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::output::Record;
+use crate::severity;
+use crate::severity::SeverityMap;
+use chrono::prelude::*;
+use failure::Fail;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+const GELF_VERSION: &str = "1.1";
+
+/// Fields consulted for the originating host when none is given explicitly
+const HOST_FIELDS: &[&str] = &["source", "host", "hostname"];
+
+/// Fields consulted for the timestamp
+const TIMESTAMP_FIELDS: &[&str] = &["timestamp", "@timestamp"];
+
+/// Fields GELF defines itself, not to be repeated as additional fields
+const RESERVED_FIELDS: &[&str] = &["_id", "full_message"];
+
+/// Name of the additional field holding a record's `id`, as GELF forbids `_id`
+const ID_FIELD: &str = "_record_id";
+
+/// Magic bytes opening each chunk of a chunked GELF message
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+
+/// Bytes of magic, message ID, sequence number and count before chunk data
+const CHUNK_HEADER: usize = 12;
+
+/// Most chunks a GELF message may be split into
+const MAX_CHUNKS: usize = 128;
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Endpoints must be given as udp://host:port or tcp://host:port, got {}",
+    _0
+)]
+pub struct EndpointError(String);
+
+/// Transport and address of a GELF input
+#[derive(Debug, Clone)]
+pub enum Endpoint {
+    Udp(String),
+    Tcp(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = EndpointError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("udp://") {
+            Ok(Endpoint::Udp(s["udp://".len()..].to_owned()))
+        } else if s.starts_with("tcp://") {
+            Ok(Endpoint::Tcp(s["tcp://".len()..].to_owned()))
+        } else {
+            Err(EndpointError(s.into()))
+        }
+    }
+}
+
+/// Which record fields become the GELF host and short message
+pub struct Mapping<'a> {
+    pub host: Option<&'a str>,
+    pub message: &'a str,
+    pub severity: Option<&'a str>,
+    pub severity_map: &'a SeverityMap,
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Adds `value` as additional field(s) named after `key`, flattening objects
+/// with dotted keys, as GELF only permits strings and numbers.
+fn additional(key: &str, value: &Value, message: &mut Map<String, Value>) {
+    let key: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+
+    match value {
+        Value::Null => (),
+        Value::Object(object) => {
+            for (child, value) in object.iter() {
+                additional(&format!("{}.{}", key, child), value, message);
+            }
+        }
+        Value::Number(_) | Value::String(_) => {
+            message.insert(field_name(&key), value.clone());
+        }
+        value => {
+            message.insert(field_name(&key), Value::from(value.to_string()));
+        }
+    }
+}
+
+fn field_name(key: &str) -> String {
+    if key == "id" {
+        ID_FIELD.to_owned()
+    } else {
+        format!("_{}", key)
+    }
+}
+
+/// Splits `message` into GELF chunks of at most `size` bytes each, or returns
+/// none if it would take more than `MAX_CHUNKS`.
+pub fn chunks(message: &[u8], size: usize) -> Option<Vec<Vec<u8>>> {
+    let payload = size - CHUNK_HEADER;
+    let count = (message.len() + payload - 1) / payload;
+    if count > MAX_CHUNKS {
+        return None;
+    }
+
+    let id = rand::random::<u64>().to_be_bytes();
+
+    Some(
+        message
+            .chunks(payload)
+            .enumerate()
+            .map(|(sequence, data)| {
+                let mut chunk = Vec::with_capacity(CHUNK_HEADER + data.len());
+                chunk.extend_from_slice(&CHUNK_MAGIC);
+                chunk.extend_from_slice(&id);
+                chunk.push(sequence as u8);
+                chunk.push(count as u8);
+                chunk.extend_from_slice(data);
+                chunk
+            })
+            .collect(),
+    )
+}
+
+/// Maps a record onto a GELF message.
+pub fn render(record: &Record, mapping: &Mapping) -> String {
+    let host = match mapping.host {
+        Some(field) => severity::lookup(record, field),
+        None => HOST_FIELDS.iter().find_map(|field| record.get(*field)),
+    };
+
+    let mut message = Map::new();
+    message.insert(String::from("version"), Value::from(GELF_VERSION));
+    message.insert(
+        String::from("host"),
+        Value::from(host.map_or_else(|| String::from("unknown"), text)),
+    );
+    message.insert(
+        String::from("short_message"),
+        Value::from(
+            severity::lookup(record, mapping.message)
+                .map(text)
+                .unwrap_or_default(),
+        ),
+    );
+
+    if let Some(full) = record.get("full_message") {
+        message.insert(String::from("full_message"), Value::from(text(full)));
+    }
+
+    let timestamp = TIMESTAMP_FIELDS
+        .iter()
+        .filter_map(|field| record.get(*field).and_then(Value::as_str))
+        .find_map(|value| DateTime::parse_from_rfc3339(value).ok());
+    if let Some(timestamp) = timestamp {
+        let seconds = timestamp.timestamp_millis() as f64 / 1000.0;
+        message.insert(String::from("timestamp"), Value::from(seconds));
+    }
+
+    if let Some(severity) = severity::mapped(record, mapping.severity, mapping.severity_map) {
+        message.insert(String::from("level"), Value::from(severity.syslog()));
+    }
+
+    for (key, value) in record.iter() {
+        let mapped = key == mapping.message
+            || mapping
+                .host
+                .map_or(HOST_FIELDS.contains(&key.as_str()), |host| key == host)
+            || TIMESTAMP_FIELDS.contains(&key.as_str())
+            || RESERVED_FIELDS.contains(&key.as_str());
+
+        if !mapped {
+            additional(key, value, &mut message);
+        }
+    }
+
+    Value::from(message).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chunks, render, Mapping};
+    use crate::severity::SeverityMap;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_render() {
+        let record = json!({
+            "_id": "abc",
+            "id": 7,
+            "message": "disk full",
+            "source": "web-1",
+            "level": 3,
+            "timestamp": "2019-10-01T12:00:00.250Z",
+            "http": {"status": 500, "tags": ["a"]},
+            "user name": "root"
+        });
+
+        let mapping = Mapping {
+            host: None,
+            message: "message",
+            severity: None,
+            severity_map: &SeverityMap::new(),
+        };
+
+        let gelf: Value =
+            serde_json::from_str(&render(record.as_object().unwrap(), &mapping)).unwrap();
+
+        assert_eq!(
+            gelf,
+            json!({
+                "version": "1.1",
+                "host": "web-1",
+                "short_message": "disk full",
+                "timestamp": 1_569_931_200.25,
+                "level": 3,
+                "_level": 3,
+                "_http.status": 500,
+                "_http.tags": "[\"a\"]",
+                "_user_name": "root",
+                "_record_id": 7
+            })
+        );
+    }
+
+    #[test]
+    fn test_chunks() {
+        let message = vec![b'x'; 25];
+        let split = chunks(&message, 22).unwrap();
+
+        assert_eq!(split.len(), 3);
+        assert_eq!(split[0].len(), 22);
+        assert_eq!(split[2].len(), 17);
+        for (i, chunk) in split.iter().enumerate() {
+            assert_eq!(chunk[..2], [0x1e, 0x0f]);
+            assert_eq!(chunk[2..10], split[0][2..10]);
+            assert_eq!(chunk[10], i as u8);
+            assert_eq!(chunk[11], 3);
+        }
+
+        assert!(chunks(&vec![b'x'; 129 * 10], 22).is_none());
+    }
+}
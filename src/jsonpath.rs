@@ -0,0 +1,248 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use failure::Fail;
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Debug, Fail)]
+#[fail(display = "Invalid JSONPath expression {}: {}", _0, _1)]
+pub struct JsonPathError(String, &'static str);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// `.name` or `['name']`
+    Child(String),
+    /// `[n]`
+    Index(usize),
+    /// `.*` or `[*]`
+    Wildcard,
+    /// `..name`
+    Descendant(String),
+}
+
+/// Subset of JSONPath: `$` followed by any number of these steps
+///
+/// - `.name` and `['name']` (or `["name"]`) for a field, where unquoted names
+///   consist of letters, digits, `_` and `-`
+/// - `[n]` for an array element, counted from 0
+/// - `.*` and `[*]` for all fields or elements
+/// - `..name` for the field at any depth
+///
+/// Anything else, such as filters, slices, unions, negative indices or script
+/// expressions, is rejected rather than matching nothing.
+#[derive(Debug, Clone)]
+pub struct JsonPath(Vec<Step>);
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// The step in brackets at the start of `s`, after the `[`, and the rest of
+/// `s` after the closing `]`
+fn bracket(s: &str) -> Result<(Step, &str), &'static str> {
+    let inner = s.trim_start();
+
+    let (step, rest) = if inner.starts_with('\'') || inner.starts_with('"') {
+        let quote = inner.chars().next().unwrap_or_default();
+        let end = inner[1..].find(quote).ok_or("unterminated quoted name")?;
+        (Step::Child(inner[1..=end].to_owned()), &inner[end + 2..])
+    } else {
+        let end = inner
+            .find(|c: char| !c.is_ascii_digit() && c != '*')
+            .unwrap_or(inner.len());
+        let (token, rest) = inner.split_at(end);
+
+        let step = match token {
+            "*" => Step::Wildcard,
+            "" if rest.is_empty() => return Err("unterminated ["),
+            "" if rest.starts_with('?') || rest.starts_with('(') => {
+                return Err("filter and script expressions are not supported")
+            }
+            "" if rest.starts_with('-') => return Err("negative indices are not supported"),
+            token => Step::Index(
+                token
+                    .parse()
+                    .map_err(|_| "expected *, an index or a quoted name in []")?,
+            ),
+        };
+        (step, rest)
+    };
+
+    let rest = rest.trim_start();
+    if rest.starts_with(']') {
+        Ok((step, &rest[1..]))
+    } else if rest.starts_with(':') || rest.starts_with(',') {
+        Err("slices and unions are not supported")
+    } else if rest.is_empty() {
+        Err("unterminated [")
+    } else {
+        Err("expected ] after the step")
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = JsonPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |reason| JsonPathError(s.into(), reason);
+
+        if !s.starts_with('$') {
+            return Err(error("expected $ at the start"));
+        }
+
+        let mut rest = &s[1..];
+        let mut steps = Vec::new();
+
+        let name = |rest: &str| -> (String, usize) {
+            let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            (rest[..end].to_owned(), end)
+        };
+
+        while !rest.is_empty() {
+            if rest.starts_with("..") {
+                let after = &rest[2..];
+                let (name, end) = name(after);
+                if name.is_empty() {
+                    return Err(error("expected a field name after .."));
+                }
+                steps.push(Step::Descendant(name));
+                rest = &after[end..];
+            } else if rest.starts_with(".*") {
+                steps.push(Step::Wildcard);
+                rest = &rest[2..];
+            } else if rest.starts_with('.') {
+                let after = &rest[1..];
+                let (name, end) = name(after);
+                if name.is_empty() {
+                    return Err(error("expected a field name after ."));
+                }
+                steps.push(Step::Child(name));
+                rest = &after[end..];
+            } else if rest.starts_with('[') {
+                let (step, after) = bracket(&rest[1..]).map_err(error)?;
+                steps.push(step);
+                rest = after;
+            } else {
+                return Err(error("expected ., .. or ["));
+            }
+        }
+
+        Ok(JsonPath(steps))
+    }
+}
+
+fn descendants<'a>(value: &'a Value, name: &str, matches: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(object) => {
+            for (key, child) in object.iter() {
+                if key == name {
+                    matches.push(child);
+                }
+                descendants(child, name, matches);
+            }
+        }
+        Value::Array(array) => {
+            for child in array.iter() {
+                descendants(child, name, matches);
+            }
+        }
+        _ => (),
+    }
+}
+
+impl JsonPath {
+    /// All values in `root` matched by the expression, in document order.
+    pub fn select<'a>(&self, root: &'a Value) -> Vec<&'a Value> {
+        self.0.iter().fold(vec![root], |values, step| {
+            let mut matches = Vec::new();
+
+            for value in values {
+                match step {
+                    Step::Child(name) => matches.extend(value.get(name)),
+                    Step::Index(index) => matches.extend(value.get(index)),
+                    Step::Wildcard => match value {
+                        Value::Object(object) => matches.extend(object.values()),
+                        Value::Array(array) => matches.extend(array.iter()),
+                        _ => (),
+                    },
+                    Step::Descendant(name) => descendants(value, name, &mut matches),
+                }
+            }
+
+            matches
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::JsonPath;
+    use serde_json::json;
+
+    #[test]
+    fn test_select() {
+        let record = json!({
+            "kubernetes": {"pod_name": "web-1", "labels": {"app": "web"}},
+            "tags": ["a", "b"],
+            "spans": [{"id": 1}, {"id": 2}]
+        });
+
+        let select = |path: &str| -> Vec<String> {
+            path.parse::<JsonPath>()
+                .unwrap()
+                .select(&record)
+                .iter()
+                .map(|value| value.to_string())
+                .collect()
+        };
+
+        assert_eq!(select("$.kubernetes.pod_name"), vec!["\"web-1\""]);
+        assert_eq!(select("$['kubernetes']['labels'].app"), vec!["\"web\""]);
+        assert_eq!(select("$.tags[1]"), vec!["\"b\""]);
+        assert_eq!(select("$.tags[*]"), vec!["\"a\"", "\"b\""]);
+        assert_eq!(select("$..id"), vec!["1", "2"]);
+        assert!(select("$.missing").is_empty());
+        assert!(select("$.tags[5]").is_empty());
+
+        assert_eq!(select("$['a]b']"), Vec::<String>::new());
+        assert_eq!(select("$[ 'tags' ][ 0 ]"), vec!["\"a\""]);
+        assert_eq!(select("$"), vec![record.to_string()]);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let reason = |path: &str| path.parse::<JsonPath>().unwrap_err().1;
+
+        assert_eq!(reason("kubernetes"), "expected $ at the start");
+        assert_eq!(reason("$.tags["), "unterminated [");
+        assert_eq!(reason("$['tags"), "unterminated quoted name");
+        assert_eq!(reason("$.tags[-1]"), "negative indices are not supported");
+        assert_eq!(reason("$.tags[0:1]"), "slices and unions are not supported");
+        assert_eq!(reason("$['a','b']"), "slices and unions are not supported");
+        assert_eq!(
+            reason("$.spans[?(@.id > 1)]"),
+            "filter and script expressions are not supported"
+        );
+        assert_eq!(
+            reason("$.tags[last]"),
+            "expected *, an index or a quoted name in []"
+        );
+        assert_eq!(reason("$..*"), "expected a field name after ..");
+        assert_eq!(reason("$.@id"), "expected a field name after .");
+        assert_eq!(reason("$.a b"), "expected ., .. or [");
+    }
+}
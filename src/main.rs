@@ -15,6 +15,9 @@
 // limitations under the License.
 
 use exitfailure::ExitFailure;
+use std::io;
+use std::path::PathBuf;
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
 /// 50shades (of Graylog)
@@ -29,10 +32,18 @@ struct Cli {
     #[structopt(long, short, default_value = "default")]
     template: String,
 
-    /// Path to custom configuration file
+    /// Path or HTTP(S) URL of a custom configuration file
     #[structopt(long, short)]
     config: Option<String>,
 
+    /// Query the node's conventional localhost address (ports 9000/9200)
+    #[structopt(long)]
+    local: bool,
+
+    /// Write each search request and response to files in this directory, credentials redacted
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+
     #[structopt(subcommand)]
     command: Command,
 }
@@ -47,73 +58,164 @@ enum Command {
     #[structopt(name = "login")]
     Login {},
 
-    /// Performs one-time query
-    #[structopt(name = "query")]
-    Query {
-        #[structopt(long = "search-from", short = "@", default_value = "2 minutes ago")]
-        from: String,
+    /// Upgrades the configuration file to the current schema
+    #[structopt(name = "migrate")]
+    Migrate {},
 
-        #[structopt(long = "search-to", short = "#", default_value = "now")]
-        to: String,
+    /// Checks the configuration, nodes, passwords and template for problems
+    #[structopt(name = "doctor")]
+    Doctor {},
 
-        #[structopt(name = "QUERY")]
-        query: Vec<String>,
+    /// Prints a shell completion script (bash, zsh, fish, powershell or elvish)
+    #[structopt(name = "completions")]
+    Completions {
+        #[structopt(name = "SHELL", raw(possible_values = "&Shell::variants()"))]
+        shell: Shell,
     },
 
+    /// Fetches a single Graylog message by ID
+    #[structopt(name = "get")]
+    Get(command::get::Args),
+
+    /// Shows the Graylog messages around a message, like grep -C
+    #[structopt(name = "context")]
+    Context(command::context::Args),
+
+    /// Runs the search behind a Graylog event (alert)
+    #[structopt(name = "event")]
+    Event(command::event::Args),
+
+    /// Performs one-time query
+    #[structopt(name = "query")]
+    Query(command::query::Args),
+
     /// Follows the tail of a query (like tail -f on a log file)
     #[structopt(name = "follow")]
-    Follow {
-        #[structopt(long = "search-from", short = "@", default_value = "10 seconds ago")]
-        from: String,
+    Follow(command::follow::Args),
 
-        #[structopt(long, default_value = "2")]
-        latency: i64,
+    /// Searches a trace ID across nodes, merged into one timeline
+    #[structopt(name = "trace")]
+    Trace(command::trace::Args),
 
-        #[structopt(long, default_value = "1000")]
-        poll: u64,
+    /// Works with output templates without querying a node
+    #[structopt(name = "template")]
+    Template(command::template::Command),
 
-        #[structopt(name = "QUERY")]
-        query: Vec<String>,
-    },
+    /// Runs queries typed at an interactive prompt against one node
+    #[structopt(name = "repl")]
+    Repl(command::repl::Args),
+
+    /// Works with the configuration file
+    #[structopt(name = "config")]
+    Config(command::config::Command),
 }
 
 pub mod config;
 pub mod datetime;
+pub mod gelf;
+pub mod jsonpath;
+pub mod metrics;
+pub mod output;
 pub mod password;
 pub mod query;
+pub mod remote;
+pub mod severity;
 pub mod template;
+pub mod tui;
 
 mod command {
+    pub mod config;
+    pub mod context;
+    pub mod doctor;
+    pub mod event;
     pub mod follow;
+    pub mod get;
     pub mod init;
     pub mod login;
+    pub mod migrate;
     pub mod query;
+    pub mod repl;
+    pub mod template;
+    pub mod trace;
 }
 
 fn main() -> Result<(), ExitFailure> {
-    let cli = Cli::from_args();
+    let mut cli = Cli::from_args();
+
+    let path = match cli.config {
+        None => config::default()?,
+        Some(path) => path,
+    };
+
+    let options = query::RequestOptions {
+        recorder: cli.record.take().map(query::Recorder::new).transpose()?,
+        ..query::RequestOptions::default()
+    };
+
+    let mut config = config::read(path.clone());
+
+    if let Ok(ref mut config) = config {
+        match cli.command {
+            Command::Init {}
+            | Command::Migrate {}
+            | Command::Doctor {}
+            | Command::Completions { .. }
+            | Command::Config(_) => (),
+            _ => {
+                cli.node = config::pick_node(config, cli.node)?;
+                cli.template = config::pick_template(config, cli.template)?;
+
+                config::load_script(config, &path)?;
+            }
+        }
+    }
 
-    let config = match cli.config {
-        None => config::default(),
-        Some(path) => Ok(path),
+    if let (true, Ok(config)) = (cli.local, config.as_mut()) {
+        if let Some(node) = config.nodes.get_mut(&cli.node) {
+            node.localize()?;
+        }
     }
-    .and_then(config::read);
 
     match cli.command {
         Command::Init {} => command::init::run(config, cli.node)?,
 
         Command::Login {} => command::login::run(config, cli.node)?,
 
-        Command::Follow {
-            from,
-            latency,
-            poll,
-            query,
-        } => command::follow::run(config, cli.node, cli.template, from, latency, poll, query)?,
+        Command::Migrate {} => command::migrate::run(&path)?,
 
-        Command::Query { from, to, query } => {
-            command::query::run(config, cli.node, cli.template, from, to, query)?
+        Command::Doctor {} => command::doctor::run(config, &path, &cli.template)?,
+
+        Command::Completions { shell } => {
+            Cli::clap().gen_completions_to("50shades", shell, &mut io::stdout())
+        }
+
+        Command::Context(args) => {
+            command::context::run(config, cli.node, cli.template, &options, args)?
+        }
+
+        Command::Event(args) => {
+            command::event::run(config, cli.node, cli.template, &options, args)?
+        }
+
+        Command::Follow(args) => {
+            command::follow::run(config, &path, cli.node, cli.template, &options, args)?
         }
+
+        Command::Get(args) => command::get::run(config, cli.node, cli.template, &options, args)?,
+
+        Command::Query(args) => {
+            command::query::run(config, cli.node, cli.template, &options, args)?
+        }
+
+        Command::Repl(args) => command::repl::run(config, cli.node, cli.template, &options, args)?,
+
+        Command::Trace(args) => {
+            command::trace::run(config, cli.node, cli.template, &options, args)?
+        }
+
+        Command::Template(command) => command::template::run(config, cli.template, command)?,
+
+        Command::Config(command) => command::config::run(config, command)?,
     }
 
     Ok(())
@@ -0,0 +1,88 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::query::ResponseError;
+use failure::Error;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use std::time::Duration;
+
+/// Statistics of a single run, as pushed to a Prometheus Pushgateway
+pub struct Metrics<'a> {
+    pub node: &'a str,
+    pub records: u64,
+    pub duration: Duration,
+    pub errors: u64,
+}
+
+/// Renders the metrics in the Prometheus text exposition format.
+fn render(metrics: &Metrics) -> String {
+    let node = metrics.node.replace('\\', "\\\\").replace('"', "\\\"");
+
+    [
+        ("fiftyshades_records", metrics.records.to_string()),
+        (
+            "fiftyshades_duration_seconds",
+            (metrics.duration.as_millis() as f64 / 1000.0).to_string(),
+        ),
+        ("fiftyshades_errors", metrics.errors.to_string()),
+    ]
+    .iter()
+    .map(|(name, value)| {
+        format!(
+            "# TYPE {} gauge\n{}{{node=\"{}\"}} {}\n",
+            name, name, node, value
+        )
+    })
+    .collect()
+}
+
+pub fn push(endpoint: &str, metrics: &Metrics) -> Result<(), Error> {
+    let mut response = Client::new()
+        .post(endpoint)
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(render(metrics))
+        .send()?;
+
+    if !response.status().is_success() {
+        return Err(ResponseError::UnexpectedStatus(response.status(), response.text()?).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render, Metrics};
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics {
+            node: "prod",
+            records: 42,
+            duration: Duration::from_millis(1500),
+            errors: 0,
+        };
+
+        assert_eq!(
+            render(&metrics),
+            "# TYPE fiftyshades_records gauge\nfiftyshades_records{node=\"prod\"} 42\n\
+             # TYPE fiftyshades_duration_seconds gauge\nfiftyshades_duration_seconds{node=\"prod\"} 1.5\n\
+             # TYPE fiftyshades_errors gauge\nfiftyshades_errors{node=\"prod\"} 0\n"
+        );
+    }
+}
@@ -0,0 +1,1750 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::Config;
+use crate::datetime::Span;
+use crate::gelf;
+use crate::gelf::Endpoint;
+use crate::jsonpath::JsonPath;
+use crate::severity;
+use crate::severity::{Severity, SeverityMap};
+use crate::template;
+use crate::tui;
+use chrono::prelude::*;
+use encoding_rs::{Encoding, UTF_8};
+use failure::{Error, Fail};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use handlebars::{html_escape, Handlebars};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
+use serde_json::map::Map;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::net::{TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+const AUTO_MAX_VALUE_LENGTH: usize = 200;
+const DEFAULT_PAGER: &str = "less";
+const CHART_WIDTH: u64 = 60;
+/// Largest UDP datagram sent, beyond which GELF messages are chunked
+const UDP_MAX_DATAGRAM: usize = 8192;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+/// Field holding the message merged by --merge-continuation-lines
+const MESSAGE_FIELD: &str = "message";
+/// Format of the time rotated output files are named after
+const SEGMENT_TIMESTAMP: &str = "%Y%m%dT%H%M%SZ";
+
+pub type Record = Map<String, Value>;
+
+#[derive(Debug, Fail)]
+#[fail(display = "Unsupported output format: {}", _0)]
+pub struct FormatError(String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "Sample rates must be between 0 and 1, got {}", _0)]
+pub struct SampleRateError(String);
+
+/// Fraction of records to output, between 0 and 1
+#[derive(Debug, Clone, Copy)]
+pub struct SampleRate(f64);
+
+impl FromStr for SampleRate {
+    type Err = SampleRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(SampleRate(rate)),
+            _ => Err(SampleRateError(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Could not interpret size {}; use e.g. 500K, 100MB or 1G",
+    _0
+)]
+pub struct ByteSizeError(String);
+
+/// Number of bytes, given with an optional K, M or G (binary) unit
+#[derive(Debug, Clone, Copy)]
+pub struct ByteSize(u64);
+
+impl FromStr for ByteSize {
+    type Err = ByteSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ByteSizeError(s.into());
+        let s = s.trim();
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let amount: u64 = s[..digits].parse().map_err(|_| error())?;
+
+        let shift = match s[digits..].trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 0,
+            "K" | "KB" | "KIB" => 10,
+            "M" | "MB" | "MIB" => 20,
+            "G" | "GB" | "GIB" => 30,
+            _ => return Err(error()),
+        };
+
+        match amount.checked_mul(1 << shift) {
+            Some(size) if size > 0 => Ok(ByteSize(size)),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "Output files cannot be rotated with the {} format", _0)]
+pub struct RotationFormatError(Format);
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "--send-to sends GELF and cannot be used with the {} format",
+    _0
+)]
+pub struct SendFormatError(Format);
+
+#[derive(Debug, Fail)]
+#[fail(display = "Unknown raw decoding {}; use base64 or text", _0)]
+pub struct RawDecodingError(String);
+
+/// How the field written by --raw-field is encoded in the record
+#[derive(Debug, Clone, Copy)]
+pub enum RawDecoding {
+    Base64,
+    Text,
+}
+
+impl FromStr for RawDecoding {
+    type Err = RawDecodingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base64" => Ok(RawDecoding::Base64),
+            "text" => Ok(RawDecoding::Text),
+            _ => Err(RawDecodingError(s.into())),
+        }
+    }
+}
+
+impl RawDecoding {
+    fn decode(self, value: &Value) -> Result<Vec<u8>, base64::DecodeError> {
+        let text = match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+
+        match self {
+            RawDecoding::Base64 => base64::decode(&text),
+            RawDecoding::Text => Ok(text.into_bytes()),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "Unknown output encoding: {}", _0)]
+pub struct EncodingError(String);
+
+/// Character encoding of the output, given by its WHATWG label
+#[derive(Debug, Clone, Copy)]
+pub struct OutputEncoding(&'static Encoding);
+
+impl FromStr for OutputEncoding {
+    type Err = EncodingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Encoding::for_label(s.as_bytes())
+            .map(|encoding| OutputEncoding(encoding.output_encoding()))
+            .ok_or_else(|| EncodingError(s.into()))
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "Command {} stopped accepting output", _0)]
+pub struct ExecClosedError(String);
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Invalid sort order in {}; use field, field:asc or field:desc",
+    _0
+)]
+pub struct SortError(String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "Conditions must be given as field=value, got {}", _0)]
+pub struct ConditionError(String);
+
+/// Record condition given as `field=value`, matching records whose (possibly
+/// dotted) field renders as exactly that value
+#[derive(Debug, Clone)]
+pub struct Condition {
+    field: String,
+    value: String,
+}
+
+impl FromStr for Condition {
+    type Err = ConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('=') {
+            Some(i) if i > 0 => Ok(Condition {
+                field: s[..i].to_owned(),
+                value: s[i + 1..].to_owned(),
+            }),
+            _ => Err(ConditionError(s.into())),
+        }
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, record: &Record) -> bool {
+        match severity::lookup(record, &self.field) {
+            Some(Value::String(s)) => *s == self.value,
+            Some(value) => serde_json::from_str::<Value>(&self.value).ok().as_ref() == Some(value),
+            None => false,
+        }
+    }
+}
+
+/// Field to sort buffered records by, given as `field[:asc|:desc]`
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    field: String,
+    descending: bool,
+}
+
+impl FromStr for SortKey {
+    type Err = SortError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, descending) = match s.rfind(':') {
+            Some(i) => match &s[i + 1..] {
+                "asc" => (&s[..i], false),
+                "desc" => (&s[..i], true),
+                _ => return Err(SortError(s.into())),
+            },
+            None => (s, false),
+        };
+
+        if field.is_empty() {
+            return Err(SortError(s.into()));
+        }
+
+        Ok(SortKey {
+            field: field.to_owned(),
+            descending,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Template,
+    Auto,
+    Logfmt,
+    Json,
+    JsonArray,
+    Html,
+    Markdown,
+    Inspect,
+    Gelf,
+    Summary,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Template => "template",
+            Format::Auto => "auto",
+            Format::Logfmt => "logfmt",
+            Format::Json => "json",
+            Format::JsonArray => "json-array",
+            Format::Html => "html",
+            Format::Markdown => "markdown",
+            Format::Inspect => "inspect",
+            Format::Gelf => "gelf",
+            Format::Summary => "summary",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl Format {
+    /// Whether the format wraps all records in a document or table meant to be
+    /// complete, or only summarizes them at the end, making it unsuitable for
+    /// unbounded output.
+    pub fn is_document(self) -> bool {
+        match self {
+            Format::JsonArray | Format::Html | Format::Markdown | Format::Summary => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = FormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "template" => Ok(Format::Template),
+            "auto" => Ok(Format::Auto),
+            "logfmt" => Ok(Format::Logfmt),
+            "json" | "ndjson" | "jsonl" => Ok(Format::Json),
+            "json-array" => Ok(Format::JsonArray),
+            "html" => Ok(Format::Html),
+            "markdown" | "markdown-table" => Ok(Format::Markdown),
+            "inspect" => Ok(Format::Inspect),
+            "gelf" => Ok(Format::Gelf),
+            "summary" => Ok(Format::Summary),
+            _ => Err(FormatError(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Output format (template, auto, logfmt, json (or ndjson), json-array, html, markdown, inspect, gelf or summary) [default: template, or gelf with --send-to]
+    #[structopt(long)]
+    pub format: Option<Format>,
+
+    /// Print every field of each record on its own line (same as --format inspect)
+    #[structopt(long)]
+    pub inspect: bool,
+
+    /// Print the values matched by a JSONPath expression (e.g. $.kubernetes.pod_name)
+    #[structopt(long, raw(conflicts_with_all = r#"&["format", "inspect", "columns"]"#))]
+    pub jsonpath: Option<JsonPath>,
+
+    /// Write only this field of each record, decoded, as raw bytes
+    #[structopt(
+        long = "raw-field",
+        raw(conflicts_with_all = r#"&["format", "inspect", "jsonpath", "columns", "encoding"]"#)
+    )]
+    pub raw_field: Option<String>,
+
+    /// How --raw-field is encoded in the record (base64 or text) [default: base64]
+    #[structopt(long = "raw-decoding", requires = "raw_field")]
+    pub raw_decoding: Option<RawDecoding>,
+
+    /// Comma-separated fields to output with the auto, html and markdown formats
+    #[structopt(long, raw(use_delimiter = "true"))]
+    pub columns: Vec<String>,
+
+    /// Shell command to pipe rendered lines into instead of stdout
+    #[structopt(long)]
+    pub exec: Option<String>,
+
+    /// Run the --exec command once per line, passing the line as argument
+    #[structopt(long = "exec-per-line")]
+    pub exec_per_line: bool,
+
+    /// Page output through $PAGER (or less) for scrolling and searching
+    #[structopt(long, conflicts_with = "exec")]
+    pub pager: bool,
+
+    /// Browse output in an interactive view, scrollable and filterable, once
+    /// all records are in
+    #[structopt(long, raw(conflicts_with_all = r#"&["exec", "pager"]"#))]
+    pub tui: bool,
+
+    /// Append output to this file instead of writing to stdout; rotated files
+    /// are named after the time they are started at
+    #[structopt(
+        long = "output",
+        short = "o",
+        parse(from_os_str),
+        raw(
+            alias = r#""output-file""#,
+            conflicts_with_all = r#"&["exec", "pager", "tui"]"#
+        )
+    )]
+    pub output_file: Option<PathBuf>,
+
+    /// Send output to a GELF input at udp://host:port or tcp://host:port
+    #[structopt(
+        long = "send-to",
+        raw(
+            conflicts_with_all = r#"&["exec", "pager", "tui", "output_file", "inspect", "jsonpath", "raw_field"]"#
+        )
+    )]
+    pub send_to: Option<Endpoint>,
+
+    /// Field to use as GELF host (defaults to source, host or hostname)
+    #[structopt(long = "gelf-host-field")]
+    pub gelf_host_field: Option<String>,
+
+    /// Field to use as GELF short message
+    #[structopt(long = "gelf-message-field", default_value = "message")]
+    pub gelf_message_field: String,
+
+    /// Write output to stdout as well as to the output file
+    #[structopt(long, requires = "output_file")]
+    pub tee: bool,
+
+    /// Gzip-compress the output file (implied by a .gz extension)
+    #[structopt(long, requires = "output_file")]
+    pub gzip: bool,
+
+    /// Write to the output file as a named pipe, created if missing, waiting
+    /// for a new reader whenever the current one goes away
+    #[structopt(long, requires = "output_file", conflicts_with = "gzip")]
+    pub fifo: bool,
+
+    /// Start a new output file once the current one holds this much (e.g. 100MB)
+    #[structopt(
+        long = "rotate-size",
+        requires = "output_file",
+        conflicts_with = "fifo"
+    )]
+    pub rotate_size: Option<ByteSize>,
+
+    /// Start a new output file once the current one is this old (e.g. 1h)
+    #[structopt(
+        long = "rotate-interval",
+        requires = "output_file",
+        conflicts_with = "fifo"
+    )]
+    pub rotate_interval: Option<Span>,
+
+    /// Character encoding of the output (e.g. latin1); unmappable characters
+    /// are written as &#NNNN; references
+    #[structopt(long)]
+    pub encoding: Option<OutputEncoding>,
+
+    /// Only output records at or above this severity (e.g. warning)
+    #[structopt(long = "min-severity")]
+    pub min_severity: Option<Severity>,
+
+    /// Field holding the record severity (defaults to level, log.level or severity)
+    #[structopt(long = "severity-field")]
+    pub severity_field: Option<String>,
+
+    /// Output a random fraction of records (e.g. 0.1 for about 10%)
+    #[structopt(long)]
+    pub sample: Option<SampleRate>,
+
+    /// Seed for --sample, making the selection reproducible
+    #[structopt(long = "sample-seed", requires = "sample")]
+    pub sample_seed: Option<u64>,
+
+    /// Merge records whose message starts with whitespace into the previous one,
+    /// e.g. for stack traces
+    #[structopt(long = "merge-continuation-lines")]
+    pub merge_continuation_lines: bool,
+
+    /// Regex identifying continuation messages instead of leading whitespace
+    #[structopt(long = "continuation-pattern", requires = "merge_continuation_lines")]
+    pub continuation_pattern: Option<Regex>,
+
+    /// Buffer all records and output them sorted by field[:desc]
+    #[structopt(long = "sort-output")]
+    pub sort_output: Option<SortKey>,
+
+    /// Output records failing to render as raw JSON with a _render_error field
+    #[structopt(long = "format-errors-as-records")]
+    pub format_errors_as_records: bool,
+
+    /// Render records with the template named after their severity, if any
+    #[structopt(long = "severity-templates")]
+    pub severity_templates: bool,
+
+    /// Text to show for fields the template outputs that are missing, null or empty
+    #[structopt(long = "null-text")]
+    pub null_text: Option<String>,
+}
+
+impl Args {
+    /// The format given, or else GELF when sending to a GELF input and the
+    /// template otherwise
+    pub fn format(&self) -> Result<Format, SendFormatError> {
+        match (self.format, &self.send_to) {
+            (None, None) => Ok(Format::Template),
+            (Some(format), None) => Ok(format),
+            (None, Some(_)) | (Some(Format::Gelf), Some(_)) => Ok(Format::Gelf),
+            (Some(format), Some(_)) => Err(SendFormatError(format)),
+        }
+    }
+
+    /// Compiled templates named after a severity (e.g. error), to be used
+    /// for records of that severity if --severity-templates is given
+    pub fn variants(&self, config: &Config) -> Result<HashMap<Severity, Handlebars>, Error> {
+        let mut variants = HashMap::new();
+
+        if self.severity_templates {
+            for severity in Severity::ALL.iter() {
+                if let Some(template) = config.templates.get(&severity.to_string()) {
+                    variants.insert(
+                        *severity,
+                        template::compile(template, config.script.as_ref())?,
+                    );
+                }
+            }
+        }
+
+        Ok(variants)
+    }
+}
+
+enum Sink {
+    /// Buffered stream flushed per line or every FLUSH_INTERVAL
+    Stream {
+        writer: BufWriter<Box<dyn Write>>,
+        line_flush: bool,
+        flushed: Instant,
+    },
+    Exec(String, Child),
+    ExecPerLine(String),
+    /// Named pipe, opened (blocking until a reader appears) on demand
+    Pipe(PathBuf, Option<File>),
+    /// One datagram per line
+    Udp(UdpSocket),
+    /// Null byte delimited lines, as GELF TCP inputs expect
+    Tcp(BufWriter<TcpStream>),
+    /// Every line goes to all sinks
+    Tee(Vec<Sink>),
+    /// File replaced by a new one when due, see `Rotation`
+    Rotating(Rotation),
+    /// Lines held back for the interactive view shown when done
+    Tui(Vec<String>),
+}
+
+/// Output file replaced by a new one, named after the time it is started at,
+/// once it holds `size` bytes or has been open for `interval`. Sizes are
+/// counted before compression.
+struct Rotation {
+    path: PathBuf,
+    gzip: bool,
+    size: Option<u64>,
+    interval: Option<Duration>,
+    segment: Box<Sink>,
+    written: u64,
+    opened: Instant,
+}
+
+/// `path` with `stamp` appended to the file name before all extensions,
+/// e.g. out.log.gz becomes out-<stamp>.log.gz
+fn segment_path(path: &Path, stamp: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let (stem, extensions) = match name.find('.') {
+        Some(i) if i > 0 => name.split_at(i),
+        _ => (name.as_str(), ""),
+    };
+
+    path.with_file_name(format!("{}-{}{}", stem, stamp, extensions))
+}
+
+impl Rotation {
+    fn new(path: &Path, gzip: bool, args: &Args) -> Result<Self, Error> {
+        Ok(Rotation {
+            path: path.to_owned(),
+            gzip,
+            size: args.rotate_size.map(|ByteSize(size)| size),
+            interval: args.rotate_interval.map(|Span(interval)| interval),
+            segment: Box::new(Rotation::open(path, gzip)?),
+            written: 0,
+            opened: Instant::now(),
+        })
+    }
+
+    /// Creates the file for a segment started now, numbered if another one
+    /// was started within the same second.
+    fn open(path: &Path, gzip: bool) -> Result<Sink, Error> {
+        let stamp = Utc::now().format(SEGMENT_TIMESTAMP).to_string();
+        let mut candidate = segment_path(path, &stamp);
+
+        for number in 1.. {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&candidate)
+            {
+                Ok(file) => return Ok(Sink::encoded(file, gzip)),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    candidate = segment_path(path, &format!("{}-{}", stamp, number));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        unreachable!("segment numbers are unbounded")
+    }
+
+    fn due(&self) -> bool {
+        self.written > 0
+            && (self.size.map_or(false, |size| self.written >= size)
+                || self
+                    .interval
+                    .map_or(false, |interval| self.opened.elapsed() >= interval))
+    }
+
+    fn write(&mut self, line: &[u8], raw: bool) -> Result<(), Error> {
+        if self.due() {
+            // Dropping the previous segment finishes its gzip stream, so each
+            // file is complete on its own
+            self.segment.flush()?;
+            *self.segment = Rotation::open(&self.path, self.gzip)?;
+            self.written = 0;
+            self.opened = Instant::now();
+        }
+
+        self.segment.write(line, raw)?;
+        self.written += line.len() as u64 + if raw { 0 } else { 1 };
+        Ok(())
+    }
+}
+
+/// Writes `data`, followed by a line break unless it is `raw`.
+fn write_data<W: Write>(writer: &mut W, data: &[u8], raw: bool) -> io::Result<()> {
+    writer.write_all(data)?;
+    if raw {
+        return Ok(());
+    }
+    writer.write_all(b"\n")
+}
+
+#[cfg(unix)]
+fn argument(line: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(line).to_owned()
+}
+
+#[cfg(not(unix))]
+fn argument(line: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(line).into_owned())
+}
+
+#[cfg(unix)]
+fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path)
+        .map(|meta| meta.file_type().is_fifo())
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn make_fifo(path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn make_fifo(_: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "named pipes are only supported on Unix",
+    ))
+}
+
+impl Sink {
+    fn new(args: &Args) -> Result<Self, Error> {
+        if args.pager {
+            let pager = env::var("PAGER").unwrap_or_else(|_| String::from(DEFAULT_PAGER));
+            return Sink::spawn(&pager);
+        }
+
+        if args.tui {
+            return Ok(Sink::Tui(Vec::new()));
+        }
+
+        match args.send_to {
+            Some(Endpoint::Udp(ref address)) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(address)?;
+                return Ok(Sink::Udp(socket));
+            }
+            Some(Endpoint::Tcp(ref address)) => {
+                return Ok(Sink::Tcp(BufWriter::new(TcpStream::connect(address)?)))
+            }
+            None => (),
+        }
+
+        match (&args.exec, &args.output_file) {
+            (Some(command), _) if args.exec_per_line => Ok(Sink::ExecPerLine(command.clone())),
+            (Some(command), _) => Sink::spawn(command),
+            (None, Some(path)) => {
+                let file = Sink::file(path, args)?;
+                if args.tee {
+                    Ok(Sink::Tee(vec![Sink::stdout(), file]))
+                } else {
+                    Ok(file)
+                }
+            }
+            (None, None) => Ok(Sink::stdout()),
+        }
+    }
+
+    /// Interactive terminals see every line as soon as it arrives
+    fn stdout() -> Self {
+        Sink::stream(Box::new(io::stdout()), atty::is(atty::Stream::Stdout))
+    }
+
+    fn file(path: &Path, args: &Args) -> Result<Self, Error> {
+        if args.fifo || is_fifo(path) {
+            if !path.exists() {
+                make_fifo(path)?;
+            }
+            return Ok(Sink::Pipe(path.to_owned(), None));
+        }
+
+        let gzip = args.gzip || path.extension().map_or(false, |ext| ext == "gz");
+        if args.rotate_size.is_some() || args.rotate_interval.is_some() {
+            return Ok(Sink::Rotating(Rotation::new(path, gzip, args)?));
+        }
+
+        // Appending keeps earlier output; gzip readers join the members
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Sink::encoded(file, gzip))
+    }
+
+    fn encoded(file: File, gzip: bool) -> Self {
+        if gzip {
+            // The encoder finishes the gzip stream when dropped
+            let encoder = GzEncoder::new(file, Compression::default());
+            Sink::stream(Box::new(encoder), false)
+        } else {
+            Sink::stream(Box::new(file), false)
+        }
+    }
+
+    fn stream(writer: Box<dyn Write>, line_flush: bool) -> Self {
+        Sink::Stream {
+            writer: BufWriter::new(writer),
+            line_flush,
+            flushed: Instant::now(),
+        }
+    }
+
+    fn spawn(command: &str) -> Result<Self, Error> {
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        Ok(Sink::Exec(command.to_owned(), child))
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> Result<(), Error> {
+        self.write(line, false)
+    }
+
+    /// Writes `line` as one message of the sink, without a line break on
+    /// streams if it is `raw`.
+    fn write(&mut self, line: &[u8], raw: bool) -> Result<(), Error> {
+        match self {
+            Sink::Stream {
+                writer,
+                line_flush,
+                flushed,
+            } => {
+                write_data(writer, line, raw)?;
+                if *line_flush || flushed.elapsed() >= FLUSH_INTERVAL {
+                    writer.flush()?;
+                    *flushed = Instant::now();
+                }
+            }
+            Sink::Exec(command, child) => {
+                let stdin = child.stdin.as_mut().expect("child stdin is piped");
+                match write_data(stdin, line, raw) {
+                    Ok(()) => (),
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                        return Err(ExecClosedError(command.clone()).into())
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Sink::ExecPerLine(command) => {
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(format!("{} \"$1\"", command))
+                    .arg("sh")
+                    .arg(argument(line))
+                    .status()?;
+
+                if !status.success() {
+                    eprintln!("Command {} failed: {}", command, status);
+                }
+            }
+            Sink::Pipe(path, file) => loop {
+                let pipe = match file {
+                    Some(pipe) => pipe,
+                    None => file.get_or_insert(OpenOptions::new().write(true).open(&path)?),
+                };
+
+                match write_data(pipe, line, raw) {
+                    Ok(()) => break,
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                        eprintln!(
+                            "Reader of {} went away, waiting for a new one",
+                            path.display()
+                        );
+                        *file = None;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            },
+            Sink::Udp(socket) => {
+                if line.len() <= UDP_MAX_DATAGRAM {
+                    socket.send(line)?;
+                } else if let Some(chunks) = gelf::chunks(line, UDP_MAX_DATAGRAM) {
+                    for chunk in chunks.iter() {
+                        socket.send(chunk)?;
+                    }
+                } else {
+                    eprintln!(
+                        "Skipping message of {} bytes, too large for chunked GELF over UDP",
+                        line.len()
+                    );
+                }
+            }
+            Sink::Tcp(stream) => {
+                stream.write_all(line)?;
+                stream.write_all(b"\0")?;
+            }
+            Sink::Tee(sinks) => {
+                for sink in sinks.iter_mut() {
+                    sink.write(line, raw)?;
+                }
+            }
+            Sink::Rotating(rotation) => rotation.write(line, raw)?,
+            Sink::Tui(lines) => {
+                let text = String::from_utf8_lossy(line);
+                lines.extend(text.lines().map(String::from));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Sink::Stream {
+                writer, flushed, ..
+            } => {
+                writer.flush()?;
+                *flushed = Instant::now();
+            }
+            Sink::Tcp(stream) => stream.flush()?,
+            Sink::Tee(sinks) => {
+                for sink in sinks.iter_mut() {
+                    sink.flush()?;
+                }
+            }
+            Sink::Rotating(rotation) => rotation.segment.flush()?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Shows the lines collected for the interactive view, printing them
+    /// instead if the terminal does not support it.
+    fn show(&mut self) {
+        if let Sink::Tui(lines) = self {
+            let lines = std::mem::replace(lines, Vec::new());
+
+            if let Err(e) = tui::browse(&lines) {
+                eprintln!("Could not show the interactive view: {}", e);
+                for line in lines.iter() {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        if let Sink::Exec(command, child) = self {
+            drop(child.stdin.take());
+            if let Err(e) = child.wait() {
+                eprintln!("Could not wait for command {}: {}", command, e);
+            }
+        }
+    }
+}
+
+/// Recognizes records continuing the message of the record before them, by
+/// leading whitespace unless given a pattern
+pub struct Continuation(Option<Regex>);
+
+impl Continuation {
+    fn matches(&self, record: &Record) -> bool {
+        let message = match record.get(MESSAGE_FIELD).and_then(Value::as_str) {
+            Some(message) => message,
+            None => return false,
+        };
+
+        match self.0 {
+            Some(ref pattern) => pattern.is_match(message),
+            None => message.starts_with(char::is_whitespace),
+        }
+    }
+
+    /// Appends the message of `record` to the one of `into` on a new line.
+    fn merge(into: &mut Record, record: &Record) {
+        let message = record
+            .get(MESSAGE_FIELD)
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+
+        let merged = match into.get(MESSAGE_FIELD).and_then(Value::as_str) {
+            Some(previous) => format!("{}\n{}", previous, message),
+            None => message.to_owned(),
+        };
+
+        into.insert(String::from(MESSAGE_FIELD), Value::from(merged));
+    }
+}
+
+pub struct Output {
+    format: Format,
+    min_severity: Option<Severity>,
+    severity_field: Option<String>,
+    severity_map: SeverityMap,
+    errors_as_records: bool,
+    null_text: Option<String>,
+    handlebars: Handlebars,
+    variants: HashMap<Severity, Handlebars>,
+    jsonpath: Option<JsonPath>,
+    raw: Option<(String, RawDecoding)>,
+    fields: Option<Vec<String>>,
+    sort: Option<SortKey>,
+    sample: Option<(f64, StdRng)>,
+    continuation: Option<Continuation>,
+    pending: Option<Record>,
+    buffer: Vec<Record>,
+    watch: Option<Condition>,
+    matches: Vec<Record>,
+    limit: Option<u64>,
+    printed: u64,
+    written: u64,
+    gelf_host_field: Option<String>,
+    gelf_message_field: String,
+    encoding: &'static Encoding,
+    sink: Sink,
+}
+
+fn is_auto_field(value: &Value) -> bool {
+    match value {
+        Value::Object(_) | Value::Array(_) => false,
+        Value::String(s) => s.len() <= AUTO_MAX_VALUE_LENGTH,
+        _ => true,
+    }
+}
+
+/// Derives the field order for `auto` output from a sample record: timestamps
+/// first, the message last and everything else in between in key order.
+fn auto_fields(record: &Record) -> Vec<String> {
+    let rank = |key: &str| match key {
+        "timestamp" | "@timestamp" => 0,
+        "message" => 2,
+        _ => 1,
+    };
+
+    let mut fields: Vec<String> = record
+        .iter()
+        .filter(|(key, value)| key.as_str() == "message" || is_auto_field(value))
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    fields.sort_by_key(|key| rank(key));
+    fields
+}
+
+fn render_auto(fields: &[String], record: &Record) -> String {
+    fields
+        .iter()
+        .filter_map(|field| match severity::lookup(record, field) {
+            None | Some(Value::Null) => None,
+            Some(Value::String(s)) => Some(format!("{}={}", field, s)),
+            Some(value) => Some(format!("{}={}", field, value)),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn html_cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => html_escape(s),
+        Some(value) => html_escape(&value.to_string()),
+    }
+}
+
+fn render_html_header(columns: &[String]) -> String {
+    let cells: String = columns
+        .iter()
+        .map(|column| format!("<th>{}</th>", html_escape(column)))
+        .collect();
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>50shades</title></head>\n<body>\n<table>\n<tr>{}</tr>",
+        cells
+    )
+}
+
+fn render_html_row(columns: &[String], record: &Record) -> String {
+    let cells: String = columns
+        .iter()
+        .map(|column| format!("<td>{}</td>", html_cell(severity::lookup(record, column))))
+        .collect();
+
+    format!("<tr>{}</tr>", cells)
+}
+
+/// Escapes a value for a Markdown table cell, which must stay on one line.
+fn markdown_cell(value: Option<&Value>) -> String {
+    let value = match value {
+        None | Some(Value::Null) => return String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    };
+
+    value
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+fn render_markdown_header(columns: &[String]) -> String {
+    let names: Vec<String> = columns
+        .iter()
+        .map(|column| markdown_cell(Some(&Value::from(column.as_str()))))
+        .collect();
+    let rule = vec!["---"; columns.len()];
+
+    format!("| {} |\n| {} |", names.join(" | "), rule.join(" | "))
+}
+
+fn render_markdown_row(columns: &[String], record: &Record) -> String {
+    let cells: Vec<String> = columns
+        .iter()
+        .map(|column| markdown_cell(severity::lookup(record, column)))
+        .collect();
+
+    format!("| {} |", cells.join(" | "))
+}
+
+fn logfmt_key(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' | '@' | '/' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn logfmt_value(value: &Value) -> String {
+    let value = match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    };
+
+    if value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"' || c == '\\')
+    {
+        format!("{:?}", value)
+    } else {
+        value
+    }
+}
+
+fn render_logfmt(record: &Record) -> String {
+    record
+        .iter()
+        .map(|(key, value)| format!("{}={}", logfmt_key(key), logfmt_value(value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn render_inspect_fields(record: &Record, indent: usize, lines: &mut Vec<String>) {
+    for (key, value) in record.iter() {
+        match value {
+            Value::Object(object) if !object.is_empty() => {
+                lines.push(format!("{:indent$}{}:", "", key, indent = indent));
+                render_inspect_fields(object, indent + 2, lines);
+            }
+            Value::String(s) => {
+                lines.push(format!("{:indent$}{}: {}", "", key, s, indent = indent))
+            }
+            value => lines.push(format!("{:indent$}{}: {}", "", key, value, indent = indent)),
+        }
+    }
+}
+
+/// Renders a record vertically, one `key: value` line per field with nested
+/// objects indented below their key, preceded by a numbered separator.
+fn render_inspect(number: u64, record: &Record) -> String {
+    let mut lines = vec![format!("-[ RECORD {} ]-", number)];
+    render_inspect_fields(record, 0, &mut lines);
+    lines.join("\n")
+}
+
+fn sort_value(value: Option<&Value>) -> (Option<f64>, String) {
+    match value {
+        Some(Value::Number(n)) => (n.as_f64(), n.to_string()),
+        Some(Value::String(s)) => (s.parse().ok(), s.clone()),
+        Some(value) => (None, value.to_string()),
+        None => (None, String::new()),
+    }
+}
+
+/// Sorts records by `key`, numerically where both values are numbers and
+/// lexicographically otherwise. Records lacking the field always sort last.
+fn sort_records(records: &mut [Record], key: &SortKey) {
+    records.sort_by(|a, b| {
+        let (a, b) = (
+            severity::lookup(a, &key.field),
+            severity::lookup(b, &key.field),
+        );
+
+        let ordering = match (a.is_some(), b.is_some()) {
+            (false, false) => return Ordering::Equal,
+            (false, true) => return Ordering::Greater,
+            (true, false) => return Ordering::Less,
+            (true, true) => match (sort_value(a), sort_value(b)) {
+                ((Some(a), _), (Some(b), _)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                ((_, a), (_, b)) => a.cmp(&b),
+            },
+        };
+
+        if key.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// Renders one line per bucket with a bar scaled to the largest count.
+fn render_chart(buckets: &[(String, u64)]) -> Vec<String> {
+    let max = buckets.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let digits = max.to_string().len();
+
+    buckets
+        .iter()
+        .map(|(key, count)| {
+            let width = (count * CHART_WIDTH).checked_div(max).unwrap_or(0);
+            let bar = "#".repeat(width as usize);
+            format!("{} {:>width$} {}", key, count, bar, width = digits)
+                .trim_end()
+                .to_owned()
+        })
+        .collect()
+}
+
+impl Output {
+    pub fn new(args: &Args, handlebars: Handlebars) -> Result<Self, Error> {
+        let format = if args.inspect {
+            Format::Inspect
+        } else {
+            args.format()?
+        };
+
+        // A document split across files would leave each of them incomplete
+        if format.is_document() && (args.rotate_size.is_some() || args.rotate_interval.is_some()) {
+            return Err(RotationFormatError(format).into());
+        }
+
+        Ok(Self {
+            format,
+            min_severity: args.min_severity,
+            severity_field: args.severity_field.clone(),
+            severity_map: SeverityMap::new(),
+            errors_as_records: args.format_errors_as_records,
+            null_text: args.null_text.clone(),
+            handlebars,
+            variants: HashMap::new(),
+            jsonpath: args.jsonpath.clone(),
+            raw: args
+                .raw_field
+                .clone()
+                .map(|field| (field, args.raw_decoding.unwrap_or(RawDecoding::Base64))),
+            fields: Some(args.columns.clone()).filter(|columns| !columns.is_empty()),
+            sort: args.sort_output.clone(),
+            sample: args.sample.map(|SampleRate(rate)| {
+                let rng = match args.sample_seed {
+                    Some(seed) => StdRng::seed_from_u64(seed),
+                    None => StdRng::from_entropy(),
+                };
+                (rate, rng)
+            }),
+            continuation: Some(Continuation(args.continuation_pattern.clone()))
+                .filter(|_| args.merge_continuation_lines),
+            pending: None,
+            buffer: Vec::new(),
+            watch: None,
+            matches: Vec::new(),
+            limit: None,
+            printed: 0,
+            written: 0,
+            gelf_host_field: args.gelf_host_field.clone(),
+            gelf_message_field: args.gelf_message_field.clone(),
+            encoding: args
+                .encoding
+                .map_or(UTF_8, |OutputEncoding(encoding)| encoding),
+            sink: Sink::new(args)?,
+        })
+    }
+
+    /// Writes `line` to the sink in the output encoding.
+    fn emit(&mut self, line: &str) -> Result<(), Error> {
+        let (bytes, _, _) = self.encoding.encode(line);
+        self.sink.write_line(&bytes)
+    }
+
+    /// Replaces the template used for rendering records.
+    pub fn set_template(&mut self, handlebars: Handlebars) {
+        self.handlebars = handlebars;
+    }
+
+    /// Replaces the per-severity templates, see `Args::variants`.
+    pub fn set_variants(&mut self, variants: HashMap<Severity, Handlebars>) {
+        self.variants = variants;
+    }
+
+    /// Replaces the node's severity translations, see `severity::mapped`.
+    pub fn set_severity_map(&mut self, map: SeverityMap) {
+        self.severity_map = map;
+    }
+
+    /// The severity of `record`, see `severity::mapped`.
+    fn severity(&self, record: &Record) -> Option<Severity> {
+        severity::mapped(
+            record,
+            self.severity_field.as_ref().map(String::as_str),
+            &self.severity_map,
+        )
+    }
+
+    /// Stops printing records once `limit` of them have been printed.
+    pub fn limit(&mut self, limit: u64) {
+        self.limit = Some(limit);
+    }
+
+    /// Collects printed records matching `condition`, see `take_matches`.
+    pub fn watch(&mut self, condition: Condition) {
+        self.watch = Some(condition);
+    }
+
+    /// Printed records matching the watched condition since the last call.
+    pub fn take_matches(&mut self) -> Vec<Record> {
+        std::mem::replace(&mut self.matches, Vec::new())
+    }
+
+    /// Number of records accepted for output so far.
+    pub fn printed(&self) -> u64 {
+        self.printed
+    }
+
+    /// Whether the record limit has been reached.
+    pub fn exhausted(&self) -> bool {
+        self.limit.map_or(false, |limit| self.printed >= limit)
+    }
+
+    /// Outputs `record`, unless it continues the previous one while merging
+    /// continuation lines, in which case it is held back until the next.
+    pub fn print(&mut self, record: &Record) -> Result<(), Error> {
+        let continuation = match self.continuation {
+            Some(ref continuation) => continuation,
+            None => return self.accept(record),
+        };
+
+        if let Some(ref mut pending) = self.pending {
+            if continuation.matches(record) {
+                Continuation::merge(pending, record);
+                return Ok(());
+            }
+        }
+
+        match self.pending.replace(record.clone()) {
+            Some(previous) => self.accept(&previous),
+            None => Ok(()),
+        }
+    }
+
+    fn accept(&mut self, record: &Record) -> Result<(), Error> {
+        if self.exhausted() {
+            return Ok(());
+        }
+
+        if let Some(min) = self.min_severity {
+            match self.severity(record) {
+                Some(severity) if severity >= min => (),
+                _ => return Ok(()),
+            }
+        }
+
+        if let Some((rate, ref mut rng)) = self.sample {
+            if !rng.gen_bool(rate) {
+                return Ok(());
+            }
+        }
+
+        self.printed += 1;
+
+        if self
+            .watch
+            .as_ref()
+            .map_or(false, |watch| watch.matches(record))
+        {
+            self.matches.push(record.clone());
+        }
+
+        if self.sort.is_some() {
+            self.buffer.push(record.clone());
+            return Ok(());
+        }
+
+        self.write(record)
+    }
+
+    /// The template for `record`, preferring the one for its severity.
+    fn template(&self, record: &Record) -> &Handlebars {
+        if self.variants.is_empty() {
+            return &self.handlebars;
+        }
+
+        self.severity(record)
+            .and_then(|severity| self.variants.get(&severity))
+            .unwrap_or(&self.handlebars)
+    }
+
+    fn render(&self, record: &Record) -> Result<String, Error> {
+        match self.null_text {
+            Some(ref text) => template::render_or(self.template(record), record, text),
+            None => template::render(self.template(record), record),
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Error> {
+        if let Some((ref field, decoding)) = self.raw {
+            if let Some(value) = severity::lookup(record, field) {
+                match decoding.decode(value) {
+                    Ok(bytes) => self.sink.write(&bytes, true)?,
+                    Err(e) => eprintln!("Could not decode {}: {}", field, e),
+                }
+            }
+
+            self.written += 1;
+            return Ok(());
+        }
+
+        if let Some(ref path) = self.jsonpath {
+            let root = Value::from(record.clone());
+            for value in path.select(&root) {
+                match value {
+                    Value::String(s) => self.emit(s)?,
+                    value => self.emit(&value.to_string())?,
+                }
+            }
+
+            self.written += 1;
+            return Ok(());
+        }
+
+        let line = match self.format {
+            Format::Template => match self.render(record) {
+                Ok(s) => s,
+                Err(ref e) if self.errors_as_records => {
+                    let mut record = record.clone();
+                    record.insert(String::from("_render_error"), Value::from(e.to_string()));
+                    Value::from(record).to_string()
+                }
+                Err(e) => {
+                    eprintln!("Could not format line: {:?}", e);
+                    return Ok(());
+                }
+            },
+            Format::Auto => {
+                let fields = self.fields.get_or_insert_with(|| auto_fields(record));
+                render_auto(fields, record)
+            }
+            Format::Logfmt => render_logfmt(record),
+            // Compact serialization escapes line breaks, keeping one record per line
+            Format::Json => Value::from(record.clone()).to_string(),
+            Format::JsonArray if self.written == 0 => format!("[{}", Value::from(record.clone())),
+            Format::JsonArray => format!(",{}", Value::from(record.clone())),
+            Format::Html => {
+                let columns = self.fields.get_or_insert_with(|| auto_fields(record));
+                let row = render_html_row(columns, record);
+
+                if self.written == 0 {
+                    format!("{}\n{}", render_html_header(columns), row)
+                } else {
+                    row
+                }
+            }
+            Format::Markdown => {
+                let columns = self.fields.get_or_insert_with(|| auto_fields(record));
+                let row = render_markdown_row(columns, record);
+
+                if self.written == 0 {
+                    format!("{}\n{}", render_markdown_header(columns), row)
+                } else {
+                    row
+                }
+            }
+            Format::Inspect => render_inspect(self.written + 1, record),
+            Format::Summary => {
+                self.written += 1;
+                return Ok(());
+            }
+            Format::Gelf => gelf::render(
+                record,
+                &gelf::Mapping {
+                    host: self.gelf_host_field.as_ref().map(String::as_str),
+                    message: &self.gelf_message_field,
+                    severity: self.severity_field.as_ref().map(String::as_str),
+                    severity_map: &self.severity_map,
+                },
+            ),
+        };
+
+        self.emit(&line)?;
+        self.written += 1;
+
+        Ok(())
+    }
+
+    /// Writes out records held back for merging or sorting, then shows the
+    /// interactive view if --tui is given.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.release()?;
+        self.sink.show();
+        Ok(())
+    }
+
+    /// Writes out records held back for merging or sorting.
+    fn release(&mut self) -> Result<(), Error> {
+        if let Some(pending) = self.pending.take() {
+            self.accept(&pending)?;
+        }
+
+        let mut records = std::mem::replace(&mut self.buffer, Vec::new());
+
+        if let Some(ref key) = self.sort {
+            sort_records(&mut records, key);
+        }
+
+        for record in records.iter() {
+            self.write(record)?;
+        }
+
+        Ok(())
+    }
+
+    /// Prints a bare value such as a count.
+    pub fn value(&mut self, value: &str) -> Result<(), Error> {
+        self.emit(value)
+    }
+
+    /// Writes out records held back, then prints the number of records
+    /// written, where they came from and how long it took, if the format is
+    /// summary.
+    pub fn summary(&mut self, source: &str, duration: Duration) -> Result<(), Error> {
+        self.release()?;
+
+        if self.format != Format::Summary {
+            return Ok(());
+        }
+
+        self.emit(&format!(
+            "{} records from {} in {:.3}s",
+            self.written,
+            source,
+            duration.as_millis() as f64 / 1000.0
+        ))
+    }
+
+    /// Prints a bar chart of labelled counts.
+    pub fn chart(&mut self, buckets: &[(String, u64)]) -> Result<(), Error> {
+        for line in render_chart(buckets) {
+            self.emit(&line)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes out any buffered output.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.sink.flush()
+    }
+
+    /// Prints a header separating the results of several queries.
+    pub fn header(&mut self, label: &str) -> Result<(), Error> {
+        self.release()?;
+
+        // Headers would break the document, so all records end up in a single one
+        if self.format.is_document() {
+            return Ok(());
+        }
+
+        self.emit(&format!("==> {} <==", label))
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let close = match self.format {
+            Format::JsonArray if self.written == 0 => String::from("[]"),
+            Format::JsonArray => String::from("]"),
+            Format::Html if self.written == 0 => format!(
+                "{}\n</table>\n</body>\n</html>",
+                render_html_header(self.fields.as_ref().map(Vec::as_slice).unwrap_or_default())
+            ),
+            Format::Html => String::from("</table>\n</body>\n</html>"),
+            Format::Markdown if self.written == 0 => {
+                render_markdown_header(self.fields.as_ref().map(Vec::as_slice).unwrap_or_default())
+            }
+            _ => return,
+        };
+
+        if let Err(e) = self.emit(&close) {
+            eprintln!("Could not close output document: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        auto_fields, render_auto, render_chart, render_html_row, render_inspect, render_logfmt,
+        render_markdown_header, render_markdown_row, segment_path, sort_records, ByteSize,
+        Continuation, RawDecoding, Record,
+    };
+    use regex::Regex;
+    use serde_json::json;
+    use std::path::Path;
+
+    #[test]
+    fn test_auto_format() {
+        let first = json!({
+            "message": "hello world",
+            "source": "web-1",
+            "timestamp": "2019-10-01T12:00:00.000Z",
+            "nested": {"a": 1},
+            "status": 200
+        });
+        let fields = auto_fields(first.as_object().unwrap());
+
+        assert_eq!(fields, vec!["timestamp", "source", "status", "message"]);
+        assert_eq!(
+            render_auto(&fields, first.as_object().unwrap()),
+            "timestamp=2019-10-01T12:00:00.000Z source=web-1 status=200 message=hello world"
+        );
+
+        let second = json!({"message": "bye", "status": null, "extra": true});
+        assert_eq!(
+            render_auto(&fields, second.as_object().unwrap()),
+            "message=bye"
+        );
+    }
+
+    #[test]
+    fn test_logfmt_format() {
+        let record = json!({
+            "message": "hello \"world\"",
+            "empty": "",
+            "bad key": 1,
+            "nested": {"a": [1, 2]},
+            "ok": true
+        });
+
+        assert_eq!(
+            render_logfmt(record.as_object().unwrap()),
+            r#"bad_key=1 empty="" message="hello \"world\"" nested="{\"a\":[1,2]}" ok=true"#
+        );
+    }
+
+    #[test]
+    fn test_chart() {
+        let buckets = vec![
+            (String::from("12:00"), 120),
+            (String::from("12:01"), 0),
+            (String::from("12:02"), 30),
+        ];
+        let lines = render_chart(&buckets);
+
+        assert_eq!(lines[0], format!("12:00 120 {}", "#".repeat(60)));
+        assert_eq!(lines[1], "12:01   0");
+        assert_eq!(lines[2], format!("12:02  30 {}", "#".repeat(15)));
+    }
+
+    #[test]
+    fn test_sort_records() {
+        let mut records: Vec<Record> = vec![
+            json!({"id": "a", "took": 20}),
+            json!({"id": "b"}),
+            json!({"id": "c", "took": "3"}),
+            json!({"id": "d", "took": 100}),
+        ]
+        .into_iter()
+        .map(|v| v.as_object().unwrap().clone())
+        .collect();
+
+        let ids = |records: &[Record]| {
+            records
+                .iter()
+                .map(|r| r["id"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        sort_records(&mut records, &"took".parse().unwrap());
+        assert_eq!(ids(&records), vec!["c", "a", "d", "b"]);
+
+        sort_records(&mut records, &"took:desc".parse().unwrap());
+        assert_eq!(ids(&records), vec!["d", "a", "c", "b"]);
+
+        assert!("took:sideways".parse::<super::SortKey>().is_err());
+    }
+
+    #[test]
+    fn test_continuation() {
+        let record = |message: &str| json!({ "message": message }).as_object().unwrap().clone();
+        let whitespace = Continuation(None);
+        let pattern = Continuation(Some(Regex::new(r"^(\s|Caused by:)").unwrap()));
+
+        assert!(whitespace.matches(&record("\tat Main.main(Main.java:3)")));
+        assert!(!whitespace.matches(&record("Caused by: java.io.IOException")));
+        assert!(pattern.matches(&record("Caused by: java.io.IOException")));
+        assert!(!pattern.matches(&record("Exception in thread \"main\"")));
+
+        let mut first = record("Exception in thread \"main\"");
+        Continuation::merge(&mut first, &record("\tat Main.main(Main.java:3)"));
+        assert_eq!(
+            first["message"],
+            "Exception in thread \"main\"\n\tat Main.main(Main.java:3)"
+        );
+    }
+
+    #[test]
+    fn test_raw_decoding() {
+        assert_eq!(
+            RawDecoding::Base64.decode(&json!("AAH/")).unwrap(),
+            vec![0, 1, 255]
+        );
+        assert!(RawDecoding::Base64.decode(&json!("not base64")).is_err());
+        assert_eq!(RawDecoding::Text.decode(&json!(42)).unwrap(), b"42");
+    }
+
+    #[test]
+    fn test_html_row() {
+        let record = json!({"message": "<b>bold</b> & co", "http": {"status": 200}});
+        let columns = vec![
+            String::from("http.status"),
+            String::from("message"),
+            String::from("missing"),
+        ];
+
+        assert_eq!(
+            render_html_row(&columns, record.as_object().unwrap()),
+            "<tr><td>200</td><td>&lt;b&gt;bold&lt;/b&gt; &amp; co</td><td></td></tr>"
+        );
+    }
+
+    #[test]
+    fn test_markdown_table() {
+        let record = json!({"message": "a | b\\c\nd", "http": {"status": 200}});
+        let columns = vec![
+            String::from("http.status"),
+            String::from("message"),
+            String::from("missing"),
+        ];
+
+        assert_eq!(
+            render_markdown_header(&columns),
+            "| http.status | message | missing |\n| --- | --- | --- |"
+        );
+        assert_eq!(
+            render_markdown_row(&columns, record.as_object().unwrap()),
+            "| 200 | a \\| b\\\\c<br>d |  |"
+        );
+    }
+
+    #[test]
+    fn test_inspect() {
+        let record: Record = serde_json::from_value(json!({
+            "message": "hello world",
+            "http": {"status": 200, "headers": {}},
+            "tags": ["a", "b"]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            render_inspect(1, &record),
+            "-[ RECORD 1 ]-\nhttp:\n  headers: {}\n  status: 200\nmessage: hello world\ntags: [\"a\",\"b\"]"
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        assert_eq!(
+            segment_path(Path::new("/var/log/out.log.gz"), "20191001T120000Z"),
+            Path::new("/var/log/out-20191001T120000Z.log.gz")
+        );
+        assert_eq!(
+            segment_path(Path::new(".hidden"), "1"),
+            Path::new(".hidden-1")
+        );
+
+        let size = |s: &str| s.parse::<ByteSize>().map(|ByteSize(size)| size).ok();
+        assert_eq!(size("512"), Some(512));
+        assert_eq!(size("100MB"), Some(100 << 20));
+        assert_eq!(size("2 g"), Some(2 << 30));
+        assert_eq!(size("0"), None);
+        assert_eq!(size("10TB"), None);
+    }
+}
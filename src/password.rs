@@ -17,15 +17,106 @@
 use dialoguer::PasswordInput;
 use failure::{Error, Fail};
 use keyring::{Keyring, KeyringError};
+use std::env;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
 use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// Environment variable selecting the password backend
+const BACKEND_VARIABLE: &str = "FIFTYSHADES_PASSWORD_BACKEND";
+
+/// Environment variable overriding the keyring service name prefix
+const SERVICE_VARIABLE: &str = "FIFTYSHADES_KEYRING_SERVICE";
+
+const DEFAULT_SERVICE: &str = "50shades";
+
+/// keyutils type of keys holding arbitrary data
+#[cfg(target_os = "linux")]
+const KEY_TYPE: &[u8] = b"user\0";
+
+/// keyutils special ID of the current user's keyring
+#[cfg(target_os = "linux")]
+const KEY_SPEC_USER_KEYRING: libc::c_long = -4;
+
+#[cfg(target_os = "linux")]
+const KEYCTL_SETPERM: libc::c_long = 5;
+#[cfg(target_os = "linux")]
+const KEYCTL_SEARCH: libc::c_long = 10;
+#[cfg(target_os = "linux")]
+const KEYCTL_READ: libc::c_long = 11;
+
+/// keyutils permissions granting everything to the possessor and the user,
+/// so that processes outside the login session can read the key, too
+#[cfg(target_os = "linux")]
+const KEY_PERMISSIONS: libc::c_long = 0x3f3f_0000;
 
 #[derive(Debug, Fail)]
-#[fail(display = "Could not store password: {}", _0)]
-struct PasswordStoreError(String);
+#[fail(display = "Unknown password backend {}; use keyring or keyutils", _0)]
+struct BackendError(String);
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Could not access the system keyring: {}
+
+If no keyring is available, set {}=keyutils to use the Linux kernel keyring
+instead.",
+    _0, _1
+)]
+struct KeyringUnavailableError(String, &'static str);
 
 #[derive(Debug, Fail)]
-#[fail(display = "Could not obtain password: {}", _0)]
-struct PasswordFetchError(String);
+#[fail(display = "Could not access the kernel keyring: {}", _0)]
+struct KeyutilsError(io::Error);
+
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug, Fail)]
+#[fail(display = "The keyutils password backend is only available on Linux")]
+struct KeyutilsUnsupportedError;
+
+/// Where passwords are stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    /// The operating system / desktop environment keyring, which is the
+    /// Secret Service on Linux
+    Keyring,
+    /// The Linux kernel key retention service, kept while the user is logged in
+    Keyutils,
+}
+
+impl FromStr for Backend {
+    type Err = BackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keyring" => Ok(Backend::Keyring),
+            "keyutils" => Ok(Backend::Keyutils),
+            _ => Err(BackendError(s.into())),
+        }
+    }
+}
+
+fn backend() -> Result<Backend, BackendError> {
+    match env::var(BACKEND_VARIABLE) {
+        Ok(backend) => backend.parse(),
+        Err(_) => Ok(Backend::Keyring),
+    }
+}
+
+/// Keyring service name for `node`, namespaced by SERVICE_VARIABLE
+fn service(node: &str) -> String {
+    let prefix = env::var(SERVICE_VARIABLE)
+        .ok()
+        .filter(|prefix| !prefix.is_empty())
+        .unwrap_or_else(|| String::from(DEFAULT_SERVICE));
+
+    format!("{}:{}", prefix, node)
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "Could not store password: {}", _0)]
+struct PasswordStoreError(String);
 
 #[derive(Debug, Fail)]
 struct NoPasswordError(String);
@@ -42,24 +133,125 @@ Please invoke `50shades --node {} login` to fix this issue.",
     }
 }
 
-pub fn get(node: &str, user: &str) -> Result<String, Error> {
-    let service = format!("50shades:{}", &node);
+fn keyring_get(node: &str, user: &str) -> Result<String, Error> {
+    let service = service(node);
     let keyring = Keyring::new(&service, user);
 
     match keyring.get_password() {
         Ok(password) => Ok(password),
         Err(KeyringError::NoPasswordFound) => Err(NoPasswordError(String::from(node)).into()),
-        Err(e) => Err(PasswordFetchError(format!("{}", e)).into()),
+        Err(e) => Err(KeyringUnavailableError(format!("{}", e), BACKEND_VARIABLE).into()),
+    }
+}
+
+/// Description of the keyutils key holding the password of `user` at `node`
+#[cfg(target_os = "linux")]
+fn key_description(node: &str, user: &str) -> Result<CString, Error> {
+    Ok(CString::new(format!("{}:{}", service(node), user))?)
+}
+
+#[cfg(target_os = "linux")]
+fn keyutils_get(node: &str, user: &str) -> Result<String, Error> {
+    let description = key_description(node, user)?;
+    let serial = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_SEARCH,
+            KEY_SPEC_USER_KEYRING,
+            KEY_TYPE.as_ptr(),
+            description.as_ptr(),
+            0,
+        )
+    };
+
+    if serial < 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ENOKEY) => Err(NoPasswordError(String::from(node)).into()),
+            _ => Err(KeyutilsError(error).into()),
+        };
+    }
+
+    let mut buffer = vec![0u8; 256];
+    loop {
+        let length = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                KEYCTL_READ,
+                serial,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+
+        if length < 0 {
+            return Err(KeyutilsError(io::Error::last_os_error()).into());
+        }
+
+        // The key's full length is returned even if the buffer was too small
+        let length = length as usize;
+        if length <= buffer.len() {
+            buffer.truncate(length);
+            return Ok(String::from_utf8(buffer)?);
+        }
+        buffer.resize(length, 0);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn keyutils_set(node: &str, user: &str, password: &str) -> Result<(), Error> {
+    let description = key_description(node, user)?;
+    let serial = unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            KEY_TYPE.as_ptr(),
+            description.as_ptr(),
+            password.as_ptr(),
+            password.len(),
+            KEY_SPEC_USER_KEYRING,
+        )
+    };
+
+    if serial < 0
+        || unsafe { libc::syscall(libc::SYS_keyctl, KEYCTL_SETPERM, serial, KEY_PERMISSIONS) } < 0
+    {
+        return Err(KeyutilsError(io::Error::last_os_error()).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn keyutils_get(_node: &str, _user: &str) -> Result<String, Error> {
+    Err(KeyutilsUnsupportedError.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn keyutils_set(_node: &str, _user: &str, _password: &str) -> Result<(), Error> {
+    Err(KeyutilsUnsupportedError.into())
+}
+
+pub fn get(node: &str, user: &str) -> Result<String, Error> {
+    match backend()? {
+        Backend::Keyring => keyring_get(node, user),
+        Backend::Keyutils => keyutils_get(node, user),
     }
 }
 
 pub fn set(node: &str, user: &str, password: &str) -> Result<(), Error> {
-    let service = format!("50shades:{}", &node);
+    if backend()? == Backend::Keyutils {
+        return keyutils_set(node, user, password);
+    }
+
+    let service = service(node);
     let keyring = Keyring::new(&service, user);
 
     match keyring.set_password(&password) {
         Ok(_) => Ok(()),
-        Err(e) => Err(PasswordStoreError(format!("{}", e)).into()),
+        Err(KeyringError::NoPasswordFound) => {
+            Err(PasswordStoreError(String::from("no password found")).into())
+        }
+        Err(e) => Err(KeyringUnavailableError(format!("{}", e), BACKEND_VARIABLE).into()),
     }
 }
 
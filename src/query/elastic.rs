@@ -14,26 +14,246 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{search, BaseUrlError, ResponseError};
+use super::{
+    http_client, take_credentials, with_headers, BaseUrlError, Client, RequestOptions,
+    ResponseError,
+};
 use crate::config::ElasticNode;
+use crate::datetime::Precision;
+use crate::output::{Output, Record};
 use crate::password;
-use crate::template;
-use failure::Error;
-use handlebars::Handlebars;
+use failure::{Error, Fail};
+use maplit::hashmap;
 use reqwest;
-use reqwest::header::ACCEPT;
-use reqwest::{Client, RequestBuilder};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::RequestBuilder;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use structopt::StructOpt;
 use url::Url;
 
+/// Hits per page of searches without a size, Elasticsearch's default cap on
+/// a single response
+const PAGE_SIZE: u32 = 10000;
+
+#[derive(Debug, Fail)]
+#[fail(display = "Script fields must be given as name=source, got {}", _0)]
+pub struct ScriptFieldError(String);
+
+/// Script field given as `name=source` on the command line
+#[derive(Debug)]
+pub struct ScriptFieldArg {
+    name: String,
+    source: String,
+}
+
+impl FromStr for ScriptFieldArg {
+    type Err = ScriptFieldError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('=') {
+            Some(i) if i > 0 => Ok(ScriptFieldArg {
+                name: s[..i].to_owned(),
+                source: s[i + 1..].to_owned(),
+            }),
+            _ => Err(ScriptFieldError(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "Expected true, false or a number of hits, got {}", _0)]
+pub struct TrackTotalHitsError(String);
+
+/// Elasticsearch's `track_total_hits`: whether to count all matching hits
+/// exactly, or up to how many
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum TrackTotalHits {
+    Enabled(bool),
+    UpTo(u64),
+}
+
+impl FromStr for TrackTotalHits {
+    type Err = TrackTotalHitsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "true" => Ok(TrackTotalHits::Enabled(true)),
+            "false" => Ok(TrackTotalHits::Enabled(false)),
+            _ => s
+                .parse()
+                .map(TrackTotalHits::UpTo)
+                .map_err(|_| TrackTotalHitsError(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Fields to include in Elasticsearch's _source (repeatable)
+    #[structopt(long = "source-include", raw(number_of_values = "1"))]
+    source_include: Vec<String>,
+
+    /// Fields to exclude from Elasticsearch's _source (repeatable)
+    #[structopt(long = "source-exclude", raw(number_of_values = "1"))]
+    source_exclude: Vec<String>,
+
+    /// Computed field given as name=painless-source (repeatable)
+    #[structopt(long = "script-field", raw(number_of_values = "1"))]
+    script_field: Vec<ScriptFieldArg>,
+
+    /// Stored field to return for indices without _source (repeatable)
+    #[structopt(long = "stored-field", raw(number_of_values = "1"))]
+    stored_field: Vec<String>,
+
+    /// Doc value field to return for indices without _source (repeatable)
+    #[structopt(long = "docvalue-field", raw(number_of_values = "1"))]
+    docvalue_field: Vec<String>,
+
+    /// Index, alias or data stream to search instead of the node URL's
+    #[structopt(long)]
+    pub index: Option<String>,
+
+    /// Add each hit's backing index and ID to the record as _meta
+    #[structopt(long = "hit-meta")]
+    pub hit_meta: bool,
+
+    /// Count matching hits exactly (true), not at all (false) or up to a number
+    #[structopt(long = "track-total-hits")]
+    pub track_total_hits: Option<TrackTotalHits>,
+
+    /// Stop scanning after this many documents per shard, at the cost of completeness
+    #[structopt(long = "terminate-after")]
+    pub terminate_after: Option<u64>,
+
+    /// Drop hits scoring below this relevance, for scored queries
+    #[structopt(long = "min-score")]
+    pub min_score: Option<f32>,
+
+    /// Return only the first hit per distinct value of this field (e.g. host.keyword)
+    #[structopt(long)]
+    pub collapse: Option<String>,
+}
+
+impl Args {
+    /// The first option given that only applies to Elasticsearch nodes, if any
+    pub fn given(&self) -> Option<&'static str> {
+        let options = [
+            ("--source-include", !self.source_include.is_empty()),
+            ("--source-exclude", !self.source_exclude.is_empty()),
+            ("--script-field", !self.script_field.is_empty()),
+            ("--stored-field", !self.stored_field.is_empty()),
+            ("--docvalue-field", !self.docvalue_field.is_empty()),
+            ("--index", self.index.is_some()),
+            ("--hit-meta", self.hit_meta),
+            ("--track-total-hits", self.track_total_hits.is_some()),
+            ("--terminate-after", self.terminate_after.is_some()),
+            ("--min-score", self.min_score.is_some()),
+            ("--collapse", self.collapse.is_some()),
+        ];
+
+        options
+            .iter()
+            .find(|(_, given)| *given)
+            .map(|(option, _)| *option)
+    }
+
+    pub fn source(&self) -> Option<Source> {
+        if self.source_include.is_empty() && self.source_exclude.is_empty() {
+            // Elasticsearch omits _source once script fields are requested
+            if !self.script_field.is_empty() {
+                return Some(Source {
+                    includes: Some(vec![String::from("*")]),
+                    excludes: None,
+                });
+            }
+
+            return None;
+        }
+
+        Some(Source {
+            includes: Some(self.source_include.clone()).filter(|v| !v.is_empty()),
+            excludes: Some(self.source_exclude.clone()).filter(|v| !v.is_empty()),
+        })
+    }
+
+    pub fn collapse(&self) -> Option<Collapse> {
+        self.collapse.clone().map(|field| Collapse { field })
+    }
+
+    pub fn stored_fields(&self) -> Option<Vec<String>> {
+        Some(self.stored_field.clone()).filter(|fields| !fields.is_empty())
+    }
+
+    pub fn docvalue_fields(&self) -> Option<Vec<String>> {
+        Some(self.docvalue_field.clone()).filter(|fields| !fields.is_empty())
+    }
+
+    pub fn script_fields(&self) -> Option<HashMap<String, ScriptField>> {
+        if self.script_field.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.script_field
+                .iter()
+                .map(|field| {
+                    (
+                        field.name.clone(),
+                        ScriptField {
+                            script: Script {
+                                source: field.source.clone(),
+                            },
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Script {
+    pub source: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScriptField {
+    pub script: Script,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Collapse {
+    pub field: String,
+}
+
+/// Date format of nanosecond precision bounds, matching `date_nanos` fields
+const NANOS_FORMAT: &str = "strict_date_optional_time_nanos";
+
 #[derive(Serialize, Debug, Default)]
 pub struct Range {
     pub gt: Option<String>,
     pub gte: Option<String>,
     pub lt: Option<String>,
     pub lte: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+impl Range {
+    /// Range from `from` (inclusive) to `to` (exclusive) at `precision`
+    pub fn between(from: String, to: String, precision: Precision) -> Self {
+        Range {
+            gte: Some(from),
+            lt: Some(to),
+            format: Some(String::from(NANOS_FORMAT)).filter(|_| precision == Precision::Nanos),
+            ..Default::default()
+        }
+    }
 }
 
 type Bool = Option<Vec<Box<Query>>>;
@@ -65,11 +285,62 @@ pub enum Query {
     Bool(QueryBool),
 }
 
+#[derive(Serialize, Debug, Default)]
+pub struct Source {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub includes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excludes: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Bounds {
+    pub min: String,
+    pub max: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    DateHistogram {
+        field: String,
+        fixed_interval: String,
+        min_doc_count: u64,
+        extended_bounds: Bounds,
+    },
+}
+
 #[derive(Serialize, Debug)]
 pub struct Request {
+    /// Maximum number of hits, or none to fetch all of them in pages
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<u32>,
     pub sort: HashMap<String, String>,
     pub query: Query,
+    #[serde(rename = "_source", skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script_fields: Option<HashMap<String, ScriptField>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stored_fields: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub docvalue_fields: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggs: Option<HashMap<String, Aggregation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track_total_hits: Option<TrackTotalHits>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terminate_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_score: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapse: Option<Collapse>,
+    /// Whether to add hit metadata to the resulting records
+    #[serde(skip)]
+    pub hit_meta: bool,
+    /// Fields to add to the resulting records
+    #[serde(skip)]
+    pub annotation: Option<Record>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -78,19 +349,64 @@ struct Hit {
     _type: String,
     _id: String,
     _score: Option<f32>,
-    _source: HashMap<String, Value>,
-    sort: Vec<u64>,
+    #[serde(default)]
+    _source: Record,
+    fields: Option<Record>,
+    /// Sort values, which are negative for dates before 1970 and for missing
+    /// fields in descending order
+    sort: Vec<Value>,
+}
+
+impl Hit {
+    /// Identifies the hit across the indices searched
+    fn key(&self) -> String {
+        format!("{}/{}", self._index, self._id)
+    }
+
+    /// The hit's source merged with any requested fields, single values
+    /// unwrapped, and optionally its metadata as `_meta`
+    fn into_record(self, request: &Request) -> Record {
+        let mut record = self._source;
+
+        if request.hit_meta {
+            record.insert(
+                String::from("_meta"),
+                serde_json::json!({"_index": self._index, "_id": self._id}),
+            );
+        }
+
+        for (name, value) in self.fields.unwrap_or_default() {
+            let value = match value {
+                Value::Array(mut values) => {
+                    if values.len() == 1 {
+                        values.remove(0)
+                    } else {
+                        Value::Array(values)
+                    }
+                }
+                value => value,
+            };
+            record.insert(name, value);
+        }
+
+        if let Some(ref annotation) = request.annotation {
+            record.extend(annotation.clone());
+        }
+
+        record
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct Total {
-    value: u32,
+    value: u64,
     relation: String,
 }
 
 #[derive(Deserialize, Debug)]
 struct Hits {
-    total: Total,
+    /// Left out by Elasticsearch when `track_total_hits` is false
+    total: Option<Total>,
     max_score: Option<f32>,
     hits: Vec<Hit>,
 }
@@ -107,8 +423,22 @@ struct Shards {
 struct Response {
     took: u32,
     timed_out: bool,
+    #[serde(default)]
+    terminated_early: bool,
     _shards: Shards,
     hits: Hits,
+    aggregations: Option<HashMap<String, Histogram>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Bucket {
+    key_as_string: String,
+    doc_count: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Histogram {
+    buckets: Vec<Bucket>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -116,8 +446,8 @@ struct Cause {
     root_cause: Option<Vec<Cause>>,
     r#type: String,
     reason: String,
-    line: u32,
-    col: u32,
+    line: Option<u32>,
+    col: Option<u32>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -126,58 +456,383 @@ struct ErrorResponse {
     status: u32,
 }
 
-pub fn node_client(node: &ElasticNode, name: &str) -> Result<RequestBuilder, Error> {
+#[derive(Deserialize, Debug)]
+struct CountResponse {
+    count: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct CountRequest<'a> {
+    query: &'a Query,
+}
+
+#[derive(Deserialize, Debug)]
+struct MultiResponse {
+    responses: Vec<Value>,
+}
+
+fn client(
+    node: &ElasticNode,
+    name: &str,
+    index: Option<&str>,
+    endpoint: &str,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
     let mut url = Url::parse(&node.url)?;
 
     match url.path_segments_mut() {
         Ok(mut path) => {
-            path.extend(&["_search"]);
+            // The index replaces the URL's last segment, unless it ends in a slash
+            if let Some(index) = index {
+                path.pop().push(index);
+            }
+            path.extend(&[endpoint]);
         }
         Err(()) => return Err(BaseUrlError.into()),
     }
 
-    let client = Client::new()
-        .post(url.as_str())
-        .header(ACCEPT, "application/json");
+    let credentials = take_credentials(name, &mut url);
+
+    let client = with_headers(
+        http_client(options)?
+            .post(url.as_str())
+            .header(ACCEPT, "application/json"),
+        &node.headers,
+    )?;
 
-    if let Some(ref user) = node.user {
+    let client = if let Some((user, password)) = credentials {
+        client.basic_auth(user, password)
+    } else if let Some(ref user) = node.user {
         let password = password::get(name, user)?;
-        Ok(client.basic_auth(user.clone(), Some(password)))
+        client.basic_auth(user.clone(), Some(password))
     } else {
-        Ok(client)
-    }
+        client
+    };
+
+    Ok(Client::new(client, options))
 }
 
-fn handle_response(response: Response, handlebars: &Handlebars) {
-    for hit in response.hits.hits.iter() {
-        match template::render(handlebars, &hit._source) {
-            Ok(s) => println!("{}", &s),
-            Err(e) => eprintln!("Could not format line: {:?}", e),
-        }
+pub fn node_client(
+    node: &ElasticNode,
+    name: &str,
+    index: Option<&str>,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
+    client(node, name, index, "_search", options)
+}
+
+pub fn multi_client(
+    node: &ElasticNode,
+    name: &str,
+    index: Option<&str>,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
+    client(node, name, index, "_msearch", options)
+}
+
+pub fn count_client(
+    node: &ElasticNode,
+    name: &str,
+    index: Option<&str>,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
+    client(node, name, index, "_count", options)
+}
+
+/// Tells the user when a search stopped before considering all documents.
+fn warn_partial(response: &Response) {
+    if response.timed_out {
+        eprintln!("Warning: search timed out, results are partial");
+    } else if response.terminated_early {
+        eprintln!("Warning: search terminated early, results are partial");
     }
 }
 
-pub fn run(
-    client: &RequestBuilder,
+fn handle_response(
+    response: Response,
     request: &Request,
-    handlebars: &Handlebars,
+    output: &mut Output,
 ) -> Result<(), Error> {
-    let client = client.try_clone().unwrap().json(request);
-    let response = match search::<Response>(client) {
-        Ok(response) => response,
+    warn_partial(&response);
+
+    for hit in response.hits.hits.into_iter() {
+        output.print(&hit.into_record(request))?;
+    }
+
+    Ok(())
+}
+
+fn error_details(reason: &str) -> String {
+    serde_json::from_str(reason)
+        .and_then(|e: ErrorResponse| Ok(format!("{}: {}", e.error.r#type, e.error.reason)))
+        .unwrap_or_else(|_| String::from("No details given"))
+}
+
+fn send<T: DeserializeOwned>(
+    client: &Client,
+    request: RequestBuilder,
+    body: Option<String>,
+) -> Result<T, Error> {
+    match client.search::<T>(request, body) {
+        Ok(response) => Ok(response),
         Err(ResponseError::UnexpectedStatus(status, reason)) => {
-            return Err(ResponseError::UnexpectedStatus(
-                status,
-                serde_json::from_str(&reason)
-                    .and_then(|e: ErrorResponse| {
-                        Ok(format!("{}: {}", e.error.r#type, e.error.reason))
-                    })
-                    .unwrap_or_else(|_| String::from("No details given")),
-            )
-            .into())
-        }
-        Err(e) => return Err(e.into()),
+            Err(ResponseError::UnexpectedStatus(status, error_details(&reason)).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Sends `body` as JSON through `client`.
+fn send_json<T: DeserializeOwned, B: Serialize>(client: &Client, body: &B) -> Result<T, Error> {
+    let request = client.request().header(CONTENT_TYPE, "application/json");
+    send::<T>(client, request, Some(serde_json::to_string(body)?))
+}
+
+/// The sort value to continue after so that hits sharing `value` are returned
+/// again, one less for numbers
+fn before(value: &Value) -> Value {
+    match value.as_i64() {
+        Some(value) => Value::from(value.saturating_sub(1)),
+        None => value.clone(),
+    }
+}
+
+/// Runs `request`, handing its hits to `handle` one page at a time. Requests
+/// without a size are fetched in pages of `PAGE_SIZE`, each continuing with
+/// `search_after` at the timestamp the previous one ended with and skipping
+/// the hits already seen there.
+fn search_pages<F>(client: &Client, request: &Request, mut handle: F) -> Result<(), Error>
+where
+    F: FnMut(Vec<Hit>) -> Result<(), Error>,
+{
+    let mut body = serde_json::to_value(request)?;
+
+    if request.size.is_some() || request.collapse.is_some() {
+        // Elasticsearch refuses search_after for collapsed searches
+        body["size"] = Value::from(request.size.unwrap_or(PAGE_SIZE));
+        let response = send_json::<Response, _>(client, &body)?;
+        warn_partial(&response);
+        return handle(response.hits.hits);
+    }
+
+    body["size"] = Value::from(PAGE_SIZE);
+    let mut at = None;
+    let mut seen = HashSet::new();
+
+    loop {
+        let response = send_json::<Response, _>(client, &body)?;
+        warn_partial(&response);
+
+        let full = response.hits.hits.len() >= PAGE_SIZE as usize;
+        let hits: Vec<Hit> = response
+            .hits
+            .hits
+            .into_iter()
+            .filter(|hit| !seen.contains(&hit.key()))
+            .collect();
+
+        match hits.last().and_then(|hit| hit.sort.first().cloned()) {
+            Some(last) => {
+                if at.as_ref() != Some(&last) {
+                    seen.clear();
+                }
+                seen.extend(
+                    hits.iter()
+                        .filter(|hit| hit.sort.first() == Some(&last))
+                        .map(Hit::key),
+                );
+                body["search_after"] = serde_json::json!([before(&last)]);
+                at = Some(last);
+            }
+            None if full => {
+                // A whole page of hits seen before can only mean more hits
+                // share one timestamp than fit in a page
+                eprintln!(
+                    "Warning: more than {} records share a timestamp, some are skipped",
+                    PAGE_SIZE
+                );
+                body["search_after"] = serde_json::json!([at]);
+                seen.clear();
+            }
+            None => (),
+        }
+
+        handle(hits)?;
+
+        if !full {
+            return Ok(());
+        }
+
+        client.page_pause();
+    }
+}
+
+pub fn run(client: &Client, request: &Request, output: &mut Output) -> Result<(), Error> {
+    search_pages(client, request, |hits| {
+        for hit in hits.into_iter() {
+            output.print(&hit.into_record(request))?;
+        }
+        Ok(())
+    })
+}
+
+/// Counts the documents matching `request`'s query through `_count`.
+pub fn count(client: &Client, request: &Request) -> Result<u64, Error> {
+    let body = CountRequest {
+        query: &request.query,
     };
-    handle_response(response, handlebars);
+    Ok(send_json::<CountResponse, _>(client, &body)?.count)
+}
+
+/// Runs `request`, returning the matching records instead of printing them.
+pub fn fetch(client: &Client, request: &Request) -> Result<Vec<Record>, Error> {
+    let mut records = Vec::new();
+    search_pages(client, request, |hits| {
+        records.extend(hits.into_iter().map(|hit| hit.into_record(request)));
+        Ok(())
+    })?;
+    Ok(records)
+}
+
+/// Runs `request` with a date histogram over `field` in `interval` buckets
+/// between `from` and `to`, printing the document count per bucket as a chart.
+pub fn timechart(
+    client: &Client,
+    mut request: Request,
+    field: &str,
+    interval: &str,
+    (from, to): (&str, &str),
+    output: &mut Output,
+) -> Result<(), Error> {
+    request.size = Some(0);
+    request.aggs = Some(hashmap! {
+        String::from("timechart") => Aggregation::DateHistogram {
+            field: field.to_owned(),
+            fixed_interval: interval.to_owned(),
+            min_doc_count: 0,
+            extended_bounds: Bounds {
+                min: from.to_owned(),
+                max: to.to_owned(),
+            },
+        }
+    });
+
+    let buckets: Vec<(String, u64)> = send_json::<Response, _>(client, &request)?
+        .aggregations
+        .and_then(|mut aggregations| aggregations.remove("timechart"))
+        .map(|histogram| histogram.buckets)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|bucket| (bucket.key_as_string, bucket.doc_count))
+        .collect();
+
+    output.chart(&buckets)
+}
+
+/// Runs several labelled requests in a single `_msearch` round trip, printing
+/// each result set under its label. Failing searches are reported and skipped.
+pub fn run_multi(
+    client: &Client,
+    requests: &[(String, Request)],
+    output: &mut Output,
+) -> Result<(), Error> {
+    let mut body = String::new();
+    for (_, request) in requests {
+        // Batched searches are not paged
+        let mut request = serde_json::to_value(request)?;
+        if request.get("size").is_none() {
+            request["size"] = Value::from(PAGE_SIZE);
+        }
+
+        body.push_str("{}\n");
+        body.push_str(&request.to_string());
+        body.push('\n');
+    }
+
+    let request = client
+        .request()
+        .header(CONTENT_TYPE, "application/x-ndjson");
+
+    let multi = send::<MultiResponse>(client, request, Some(body))?;
+
+    for (entry, response) in requests.iter().zip(multi.responses) {
+        let (label, request) = entry;
+        output.header(label)?;
+
+        match serde_json::from_value::<Response>(response.clone()) {
+            Ok(response) => handle_response(response, request, output)?,
+            Err(_) => eprintln!(
+                "Query {} failed: {}",
+                label,
+                error_details(&response.to_string())
+            ),
+        }
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::{before, Response};
+    use serde_json::json;
+
+    #[test]
+    fn test_response_without_total() {
+        let response = json!({
+            "took": 3,
+            "timed_out": false,
+            "_shards": {"total": 1, "successful": 1, "skipped": 0, "failed": 0},
+            "hits": {
+                "max_score": null,
+                "hits": [{
+                    "_index": "logs",
+                    "_type": "_doc",
+                    "_id": "1",
+                    "_score": null,
+                    "_source": {"message": "disk full"},
+                    "sort": [1569931200000u64]
+                }]
+            }
+        });
+
+        let response: Response = serde_json::from_value(response).unwrap();
+        assert!(response.hits.total.is_none());
+        assert!(response.hits.max_score.is_none());
+        assert_eq!(response.hits.hits.len(), 1);
+    }
+
+    #[test]
+    fn test_negative_sort_values() {
+        let response = json!({
+            "took": 1,
+            "timed_out": false,
+            "_shards": {"total": 1, "successful": 1, "skipped": 0, "failed": 0},
+            "hits": {
+                "max_score": null,
+                "hits": [{
+                    "_index": "logs",
+                    "_type": "_doc",
+                    "_id": "1",
+                    "_score": null,
+                    "_source": {"message": "no timestamp"},
+                    "sort": [-9223372036854775808i64]
+                }, {
+                    "_index": "logs",
+                    "_type": "_doc",
+                    "_id": "2",
+                    "_score": null,
+                    "_source": {"message": "moon landing"},
+                    "sort": [-14159040000i64]
+                }]
+            }
+        });
+
+        let response: Response = serde_json::from_value(response).unwrap();
+        let sort: Vec<&serde_json::Value> =
+            response.hits.hits.iter().map(|hit| &hit.sort[0]).collect();
+        assert_eq!(before(sort[0]), json!(-9223372036854775808i64));
+        assert_eq!(before(sort[1]), json!(-14159040001i64));
+        assert_eq!(before(&json!("web-1")), json!("web-1"));
+    }
+}
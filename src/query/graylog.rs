@@ -14,25 +14,139 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{search, BaseUrlError, ResponseError};
+use super::{
+    http_client, take_credentials, with_headers, BaseUrlError, Client, RequestOptions,
+    ResponseError,
+};
 use crate::config::GraylogNode;
+use crate::output;
+use crate::output::{Format, Output};
 use crate::password;
-use crate::template;
+use crate::severity;
+use crate::severity::Severity;
 use chrono::prelude::*;
 use chrono::Utc;
-use failure::Error;
-use handlebars::Handlebars;
+use failure::{Error, Fail};
 use reqwest;
-use reqwest::header::ACCEPT;
-use reqwest::Client;
-use reqwest::RequestBuilder;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{Method, RequestBuilder};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::map::Map;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::hash::BuildHasher;
+use std::str::FromStr;
+use structopt::StructOpt;
 use url::Url;
 
+/// Messages requested at once when fetching all matches of a search
+const PAGE_SIZE: usize = 1000;
+
+/// Sort order of paged searches, whose pages are put back together newest last
+const PAGE_SORT: &str = "timestamp:desc";
+
+/// Parameters set by 50shades itself, which --param may still override
+const RESERVED_PARAMS: &[&str] = &["query", "from", "to", "keyword", "limit", "offset"];
+
+/// ANSI styling around the parts of a field matching the query, see --highlight
+const HIGHLIGHT_START: &str = "\x1b[1;33m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+#[derive(Debug, Fail)]
+#[fail(display = "Parameters must be given as key=value, got {}", _0)]
+pub struct ParamError(String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "--highlight only applies to template lines written to the terminal")]
+pub struct HighlightError;
+
+/// Raw search parameter given as `key=value` on the command line
+#[derive(Debug)]
+pub struct Param {
+    key: String,
+    value: String,
+}
+
+impl FromStr for Param {
+    type Err = ParamError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('=') {
+            Some(i) if i > 0 => Ok(Param {
+                key: s[..i].to_owned(),
+                value: s[i + 1..].to_owned(),
+            }),
+            _ => Err(ParamError(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Extra Graylog search parameter given as key=value (repeatable)
+    #[structopt(long, raw(number_of_values = "1"))]
+    param: Vec<Param>,
+
+    /// Add common message fields under stable names as _common
+    #[structopt(long = "common-fields")]
+    pub common_fields: bool,
+
+    /// Emphasize the parts of fields matching the query, where Graylog reports them
+    #[structopt(long)]
+    pub highlight: bool,
+}
+
+impl Args {
+    /// The first option given that only applies to Graylog nodes, if any
+    pub fn given(&self) -> Option<&'static str> {
+        if !self.param.is_empty() {
+            Some("--param")
+        } else if self.common_fields {
+            Some("--common-fields")
+        } else if self.highlight {
+            Some("--highlight")
+        } else {
+            None
+        }
+    }
+
+    /// Whether to highlight matches, which only template lines printed to a
+    /// terminal can show; other formats and destinations are refused.
+    pub fn highlighted(&self, output: &output::Args) -> Result<bool, Error> {
+        if !self.highlight {
+            return Ok(false);
+        }
+
+        if output.format()? != Format::Template
+            || output.inspect
+            || output.jsonpath.is_some()
+            || output.raw_field.is_some()
+            || output.exec.is_some()
+            || output.pager
+            || output.tui
+            || output.output_file.is_some()
+        {
+            return Err(HighlightError.into());
+        }
+
+        Ok(atty::is(atty::Stream::Stdout))
+    }
+
+    /// The extra search parameters, warning about any overriding our own.
+    pub fn params(&self) -> HashMap<&str, String> {
+        self.param
+            .iter()
+            .map(|param| {
+                if RESERVED_PARAMS.contains(&param.key.as_str()) {
+                    eprintln!("Warning: --param overrides the {} parameter", param.key);
+                }
+                (param.key.as_str(), param.value.clone())
+            })
+            .collect()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct Response {
     from: Option<DateTime<Utc>>,
@@ -47,55 +161,275 @@ struct Response {
     query: Option<String>,
 }
 
+/// Typed view of the fields most Graylog messages share, independent of the
+/// exact names the inputs use for them
+#[derive(Serialize, Debug)]
+struct Common {
+    timestamp: Option<String>,
+    source: Option<String>,
+    message: Option<String>,
+    level: Option<u64>,
+    facility: Option<String>,
+    /// Graylog's internal gl2_ fields, without the prefix
+    gl2: Map<String, Value>,
+}
+
+impl Common {
+    fn of(record: &Map<String, Value>) -> Common {
+        let first = |fields: &[&str]| {
+            fields
+                .iter()
+                .filter_map(|field| record.get(*field))
+                .find_map(|value| match value {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Null => None,
+                    value => Some(value.to_string()),
+                })
+        };
+
+        Common {
+            timestamp: first(&["timestamp", "@timestamp"]),
+            source: first(&["source", "host", "hostname"]),
+            message: first(&["message", "short_message"]),
+            level: severity::of(record, None).map(Severity::syslog),
+            facility: first(&["facility"]),
+            gl2: record
+                .iter()
+                .filter(|(key, _)| key.starts_with("gl2_"))
+                .map(|(key, value)| (key["gl2_".len()..].to_owned(), value.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MessageResponse {
+    message: Map<String, Value>,
+    index: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Event {
+    pub id: String,
+    pub event_definition_id: String,
+    pub message: String,
+    pub timerange_start: Option<String>,
+    pub timerange_end: Option<String>,
+    #[serde(default)]
+    pub source_streams: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EventSummary {
+    event: Event,
+}
+
+#[derive(Deserialize, Debug)]
+struct EventsResponse {
+    events: Vec<EventSummary>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EventConfig {
+    #[serde(default)]
+    pub query: String,
+    #[serde(default)]
+    pub streams: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EventDefinition {
+    pub title: String,
+    pub config: EventConfig,
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "Event {} could not be found", _0)]
+pub struct MissingEventError(String);
+
 #[derive(Serialize, Deserialize, Debug)]
 struct ErrorResponse {
     r#type: String,
     message: String,
 }
 
-pub fn node_client(node: &GraylogNode, name: &str) -> Result<RequestBuilder, Error> {
+fn client(
+    node: &GraylogNode,
+    name: &str,
+    method: Method,
+    segments: &[&str],
+    options: &RequestOptions,
+) -> Result<Client, Error> {
     let mut url = Url::parse(&node.url)?;
 
     match url.path_segments_mut() {
         Ok(mut path) => {
-            path.extend(&["search", "universal", "absolute"]);
+            path.extend(segments);
         }
         Err(()) => return Err(BaseUrlError.into()),
     }
 
-    let password = password::get(name, &node.user)?;
+    let (user, password) = match take_credentials(name, &mut url) {
+        Some(credentials) => credentials,
+        None => (node.user.clone(), Some(password::get(name, &node.user)?)),
+    };
+
+    let client = http_client(options)?
+        .request(method, url.as_str())
+        // Graylog rejects modifying requests without this CSRF guard
+        .header("X-Requested-By", "50shades")
+        .basic_auth(user, password)
+        .header(ACCEPT, "application/json");
 
-    Ok(Client::new()
-        .get(url.as_str())
-        .basic_auth(node.user.clone(), Some(password))
-        .header(ACCEPT, "application/json"))
+    Ok(Client::new(with_headers(client, &node.headers)?, options))
 }
 
-fn handle_response(response: Response, handlebars: &Handlebars) {
-    if let Some(mut messages) = response.messages {
-        messages.reverse();
-        for message in messages.iter() {
-            if let Some(Value::Object(m)) = message.get("message") {
-                match template::render(handlebars, &m) {
-                    Ok(s) => println!("{}", &s),
-                    Err(e) => eprintln!("Could not format line: {:?}", e),
-                }
+pub fn node_client(
+    node: &GraylogNode,
+    name: &str,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
+    client(
+        node,
+        name,
+        Method::GET,
+        &["search", "universal", "absolute"],
+        options,
+    )
+}
+
+/// Client for searches over a natural language time range evaluated by Graylog
+pub fn keyword_client(
+    node: &GraylogNode,
+    name: &str,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
+    client(
+        node,
+        name,
+        Method::GET,
+        &["search", "universal", "keyword"],
+        options,
+    )
+}
+
+pub fn message_client(
+    node: &GraylogNode,
+    name: &str,
+    index: &str,
+    id: &str,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
+    client(node, name, Method::GET, &["messages", index, id], options)
+}
+
+pub fn event_client(
+    node: &GraylogNode,
+    name: &str,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
+    client(node, name, Method::POST, &["events", "search"], options)
+}
+
+pub fn definition_client(
+    node: &GraylogNode,
+    name: &str,
+    id: &str,
+    options: &RequestOptions,
+) -> Result<Client, Error> {
+    client(
+        node,
+        name,
+        Method::GET,
+        &["events", "definitions", id],
+        options,
+    )
+}
+
+/// The response's message summaries in chronological order
+fn summaries(response: Response) -> Vec<Map<String, Value>> {
+    let mut messages = response.messages.unwrap_or_default();
+    messages.reverse();
+    messages
+}
+
+/// Marks the given ranges of `value`, as `{"start": n, "length": n}` objects
+/// counting characters, with ANSI styling.
+fn highlight(value: &str, ranges: &[Value]) -> String {
+    let mut ranges: Vec<(usize, usize)> = ranges
+        .iter()
+        .filter_map(|range| {
+            let start = range.get("start")?.as_u64()? as usize;
+            let length = range.get("length")?.as_u64()? as usize;
+            Some((start, start + length))
+        })
+        .collect();
+    ranges.sort_unstable();
+
+    let mut highlighted = String::new();
+    let mut ranges = ranges.into_iter().peekable();
+    let mut end = None;
+
+    for (i, c) in value.chars().enumerate() {
+        if end == Some(i) {
+            highlighted.push_str(HIGHLIGHT_END);
+            end = None;
+        }
+
+        while let Some(&(start, stop)) = ranges.peek() {
+            if start > i {
+                break;
+            }
+            ranges.next();
+
+            if stop > i && end.is_none() {
+                highlighted.push_str(HIGHLIGHT_START);
+                end = Some(stop);
+            } else if let Some(current) = end {
+                end = Some(current.max(stop));
             }
         }
+
+        highlighted.push(c);
     }
+
+    if end.is_some() {
+        highlighted.push_str(HIGHLIGHT_END);
+    }
+
+    highlighted
 }
 
-pub fn run<S: BuildHasher>(
-    client: &RequestBuilder,
-    query: &HashMap<&str, String, S>,
-    handlebars: &Handlebars,
-) -> Result<(), Error> {
-    let tuples: Vec<(&&str, &String)> = query.iter().collect();
-    let client = client.try_clone().unwrap().query(&tuples);
-    let response = match search::<Response>(client) {
-        Ok(response) => response,
+/// The message of a summary, with the parts matching the query highlighted
+/// if requested and reported by Graylog.
+fn message_of(mut summary: Map<String, Value>, highlighted: bool) -> Option<Map<String, Value>> {
+    let mut message = match summary.remove("message") {
+        Some(Value::Object(message)) => message,
+        _ => return None,
+    };
+
+    if let (true, Some(Value::Object(fields))) = (highlighted, summary.get("highlight_ranges")) {
+        for (field, ranges) in fields.iter() {
+            if let (Some(Value::String(value)), Value::Array(ranges)) =
+                (message.get_mut(field), ranges)
+            {
+                *value = highlight(value, ranges);
+            }
+        }
+    }
+
+    Some(message)
+}
+
+fn send<T: DeserializeOwned>(
+    client: &Client,
+    request: RequestBuilder,
+    body: Option<String>,
+) -> Result<T, Error> {
+    match client.search::<T>(request, body) {
+        Ok(response) => Ok(response),
         Err(ResponseError::UnexpectedStatus(status, reason)) => {
-            return Err(ResponseError::UnexpectedStatus(
+            Err(ResponseError::UnexpectedStatus(
                 status,
                 serde_json::from_str(&reason)
                     .and_then(|e: ErrorResponse| Ok(e.message))
@@ -103,12 +437,163 @@ pub fn run<S: BuildHasher>(
             )
             .into())
         }
-        Err(e) => return Err(e.into()),
-    };
-    handle_response(response, handlebars);
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Fetches a single message from `client`'s index.
+pub fn message(client: &Client) -> Result<Map<String, Value>, Error> {
+    Ok(send::<MessageResponse>(client, client.request(), None)?.message)
+}
+
+/// Fetches and prints a single message from `client`'s index.
+pub fn get(client: &Client, output: &mut Output) -> Result<(), Error> {
+    output.print(&message(client)?)
+}
+
+/// Looks up a single event (alert) by its ID.
+pub fn event(client: &Client, id: &str) -> Result<Event, Error> {
+    let request = client.request().header(CONTENT_TYPE, "application/json");
+    let body = serde_json::json!({
+        "query": format!("id:{}", id),
+        "page": 1,
+        "per_page": 1,
+        "timerange": {"type": "relative", "range": 0}
+    });
+
+    send::<EventsResponse>(client, request, Some(body.to_string()))?
+        .events
+        .into_iter()
+        .next()
+        .map(|summary| summary.event)
+        .ok_or_else(|| MissingEventError(id.to_owned()).into())
+}
+
+pub fn definition(client: &Client) -> Result<EventDefinition, Error> {
+    send::<EventDefinition>(client, client.request(), None)
+}
+
+/// Runs `query` and prints the matching records, see `records`.
+pub fn run<S: BuildHasher>(
+    client: &Client,
+    query: &HashMap<&str, String, S>,
+    common: bool,
+    highlighted: bool,
+    annotation: Option<&Map<String, Value>>,
+    output: &mut Output,
+) -> Result<(), Error> {
+    for record in records(client, query, common, highlighted, annotation)? {
+        output.print(&record)?;
+    }
+
     Ok(())
 }
 
+/// Runs `query`, returning the matching records with their `Common` fields
+/// added as `_common` if `common` is set, and the `annotation` fields.
+pub fn records<S: BuildHasher>(
+    client: &Client,
+    query: &HashMap<&str, String, S>,
+    common: bool,
+    highlighted: bool,
+    annotation: Option<&Map<String, Value>>,
+) -> Result<Vec<Map<String, Value>>, Error> {
+    let messages = fetch_summaries(client, query)?
+        .into_iter()
+        .filter_map(|summary| message_of(summary, highlighted));
+
+    let mut records = Vec::new();
+    for mut record in messages {
+        if common {
+            let value = serde_json::to_value(Common::of(&record))?;
+            record.insert(String::from("_common"), value);
+        }
+        if let Some(annotation) = annotation {
+            record.extend(annotation.clone());
+        }
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Counts the messages matching `query` from the search's total.
+pub fn count<S: BuildHasher>(
+    client: &Client,
+    query: &HashMap<&str, String, S>,
+) -> Result<u64, Error> {
+    let mut tuples: Vec<(&&str, &String)> = query.iter().filter(|(k, _)| **k != "limit").collect();
+    let limit = String::from("1");
+    tuples.push((&"limit", &limit));
+    let request = client.request().query(&tuples);
+    Ok(send::<Response>(client, request, None)?
+        .total_results
+        .unwrap_or(0))
+}
+
+/// Runs `query`, returning the matching records instead of printing them.
+pub fn fetch<S: BuildHasher>(
+    client: &Client,
+    query: &HashMap<&str, String, S>,
+) -> Result<Vec<Map<String, Value>>, Error> {
+    Ok(fetch_summaries(client, query)?
+        .into_iter()
+        .filter_map(|summary| message_of(summary, false))
+        .collect())
+}
+
+/// Runs `query`, returning the message summaries. Unlimited queries are
+/// fetched in pages of `PAGE_SIZE` to get past the server's cap on a single
+/// response.
+fn fetch_summaries<S: BuildHasher>(
+    client: &Client,
+    query: &HashMap<&str, String, S>,
+) -> Result<Vec<Map<String, Value>>, Error> {
+    let tuples: Vec<(&&str, &String)> = query.iter().collect();
+
+    if query.get("limit").map_or(false, |limit| limit != "0") {
+        let request = client.request().query(&tuples);
+        return Ok(summaries(send::<Response>(client, request, None)?));
+    }
+
+    if query.contains_key("sort") {
+        eprintln!("Warning: --param sort is ignored by searches fetched in pages");
+    }
+
+    let mut tuples: Vec<(&&str, &String)> = tuples
+        .into_iter()
+        .filter(|(k, _)| **k != "limit" && **k != "sort")
+        .collect();
+    let limit = PAGE_SIZE.to_string();
+    let sort = PAGE_SORT.to_owned();
+    tuples.push((&"limit", &limit));
+    tuples.push((&"sort", &sort));
+
+    let mut pages = Vec::new();
+    loop {
+        if !pages.is_empty() {
+            client.page_pause();
+        }
+
+        let offset = (pages.len() * PAGE_SIZE).to_string();
+        let request = client
+            .request()
+            .query(&tuples)
+            .query(&[("offset", &offset)]);
+
+        let page = summaries(send::<Response>(client, request, None)?);
+        let last = page.len() < PAGE_SIZE;
+        pages.push(page);
+
+        if last {
+            break;
+        }
+    }
+
+    // Each page is chronological, but the pages come newest first as sorted
+    Ok(pages.into_iter().rev().flatten().collect())
+}
+
 pub fn assign_query<S: BuildHasher>(query: &[String], params: &mut HashMap<&str, String, S>) {
     if !query.is_empty() {
         params.insert("query", query.join(" "));
@@ -116,3 +601,61 @@ pub fn assign_query<S: BuildHasher>(query: &[String], params: &mut HashMap<&str,
         params.insert("query", String::from("*"));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{highlight, Common};
+    use serde_json::json;
+
+    #[test]
+    fn test_common() {
+        let record = json!({
+            "@timestamp": "2019-10-01T12:00:00.000Z",
+            "host": "web-1",
+            "short_message": "disk full",
+            "level": 3,
+            "gl2_source_input": "5d93",
+            "gl2_remote_ip": "10.0.0.1"
+        });
+
+        let common = Common::of(record.as_object().unwrap());
+        assert_eq!(
+            common.timestamp.as_ref().map(String::as_str),
+            Some("2019-10-01T12:00:00.000Z")
+        );
+        assert_eq!(common.source.as_ref().map(String::as_str), Some("web-1"));
+        assert_eq!(
+            common.message.as_ref().map(String::as_str),
+            Some("disk full")
+        );
+        assert_eq!(common.level, Some(3));
+        assert_eq!(common.facility, None);
+        assert_eq!(common.gl2.len(), 2);
+        assert_eq!(common.gl2["remote_ip"], "10.0.0.1");
+    }
+
+    #[test]
+    fn test_highlight() {
+        let ranges = |ranges: serde_json::Value| ranges.as_array().unwrap().clone();
+
+        assert_eq!(
+            highlight(
+                "disk full on web-1",
+                &ranges(json!([{"start": 13, "length": 5}, {"start": 0, "length": 4}]))
+            ),
+            "\x1b[1;33mdisk\x1b[0m full on \x1b[1;33mweb-1\x1b[0m"
+        );
+        assert_eq!(
+            highlight("äöü", &ranges(json!([{"start": 1, "length": 1}]))),
+            "ä\x1b[1;33mö\x1b[0mü"
+        );
+        assert_eq!(
+            highlight(
+                "abcdef",
+                &ranges(json!([{"start": 1, "length": 3}, {"start": 2, "length": 1}]))
+            ),
+            "a\x1b[1;33mbcd\x1b[0mef"
+        );
+        assert_eq!(highlight("abc", &[]), "abc");
+    }
+}
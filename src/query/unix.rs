@@ -0,0 +1,355 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::ResponseError;
+use percent_encoding::percent_decode_str;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, TRANSFER_ENCODING};
+use reqwest::{Request, StatusCode};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Scheme of node URLs reaching the server through a Unix domain socket,
+/// whose percent-encoded path takes the place of the host
+pub const SCHEME: &str = "http+unix";
+
+/// Most headers a response is expected to have
+const MAX_HEADERS: usize = 64;
+
+/// Time an exchange may take without a deadline, as HTTP requests do by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn malformed(reason: &str) -> ResponseError {
+    ResponseError::MalformedResponse(reason.to_owned())
+}
+
+/// The socket `url` reaches the server through, if it is an `http+unix` URL
+pub fn socket(url: &str) -> Option<PathBuf> {
+    let url = Url::parse(url).ok().filter(|url| url.scheme() == SCHEME)?;
+
+    url.host_str()
+        .filter(|host| !host.is_empty())
+        .map(|host| PathBuf::from(percent_decode_str(host).decode_utf8_lossy().into_owned()))
+}
+
+/// `request` with `body` as HTTP/1.1, asking the server to close the
+/// connection after responding
+fn encode(request: &Request, body: &str) -> Vec<u8> {
+    let url = request.url();
+    let mut target = format!("/{}", url.path().trim_start_matches('/'));
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+
+    let mut encoded = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\n",
+        request.method(),
+        target
+    )
+    .into_bytes();
+
+    for (name, value) in request.headers() {
+        encoded.extend_from_slice(name.as_str().as_bytes());
+        encoded.extend_from_slice(b": ");
+        encoded.extend_from_slice(value.as_bytes());
+        encoded.extend_from_slice(b"\r\n");
+    }
+
+    encoded.extend_from_slice(
+        format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .as_bytes(),
+    );
+    encoded.extend_from_slice(body.as_bytes());
+    encoded
+}
+
+/// The data of a chunked body
+fn dechunk(mut chunks: &[u8]) -> Result<Vec<u8>, ResponseError> {
+    let mut body = Vec::new();
+
+    loop {
+        let (start, size) = match httparse::parse_chunk_size(chunks) {
+            Ok(httparse::Status::Complete(chunk)) => chunk,
+            _ => return Err(malformed("invalid chunk size")),
+        };
+        if size == 0 {
+            return Ok(body);
+        }
+
+        let end = start + size as usize;
+        body.extend_from_slice(
+            chunks
+                .get(start..end)
+                .ok_or_else(|| malformed("truncated chunk"))?,
+        );
+        // Each chunk's data ends in CRLF
+        chunks = chunks
+            .get(end + 2..)
+            .ok_or_else(|| malformed("truncated chunk"))?;
+    }
+}
+
+/// Status, headers and body of a complete HTTP/1.1 response
+fn decode(response: &[u8]) -> Result<(StatusCode, HeaderMap, String), ResponseError> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parsed = httparse::Response::new(&mut headers);
+
+    let length = match parsed.parse(response) {
+        Ok(httparse::Status::Complete(length)) => length,
+        Ok(httparse::Status::Partial) => return Err(malformed("incomplete header")),
+        Err(e) => return Err(malformed(&e.to_string())),
+    };
+
+    let status = parsed
+        .code
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(|| malformed("invalid status"))?;
+
+    let mut map = HeaderMap::new();
+    for header in parsed.headers.iter() {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(header.name.as_bytes()),
+            HeaderValue::from_bytes(header.value),
+        ) {
+            map.append(name, value);
+        }
+    }
+
+    let header = |name| {
+        map.get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+    };
+    let body = &response[length..];
+    let body = if header(TRANSFER_ENCODING).map_or(false, |value| value.contains("chunked")) {
+        dechunk(body)?
+    } else {
+        match header(CONTENT_LENGTH) {
+            Some(length) => {
+                let length: usize = length
+                    .parse()
+                    .map_err(|_| malformed("invalid content length"))?;
+                body.get(..length)
+                    .ok_or_else(|| malformed("truncated body"))?
+                    .to_vec()
+            }
+            None => body.to_vec(),
+        }
+    };
+
+    let body = String::from_utf8(body).map_err(|_| malformed("body is not UTF-8"))?;
+    Ok((status, map, body))
+}
+
+/// Time left until `until`, failing once it has passed
+fn remaining(until: Instant) -> io::Result<Duration> {
+    let now = Instant::now();
+    if until > now {
+        // Zero would disable the socket timeouts instead
+        Ok((until - now).max(Duration::from_millis(1)))
+    } else {
+        Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out"))
+    }
+}
+
+/// Sends `request` with `body` over the Unix domain socket at `path`, giving
+/// up at `deadline`, if any, with `ResponseError::DeadlineExceeded`, or else
+/// after DEFAULT_TIMEOUT.
+#[cfg(unix)]
+pub fn send(
+    path: &Path,
+    request: &Request,
+    body: &str,
+    deadline: Option<Instant>,
+) -> Result<(StatusCode, HeaderMap, String), ResponseError> {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let until = deadline.unwrap_or_else(|| Instant::now() + DEFAULT_TIMEOUT);
+
+    let exchange = || -> io::Result<Vec<u8>> {
+        let mut stream = UnixStream::connect(path)?;
+        stream.set_write_timeout(Some(remaining(until)?))?;
+        stream.write_all(&encode(request, body))?;
+
+        // Each read waits for the rest of the time only, so that a server
+        // trickling its response cannot outlast it
+        let mut response = Vec::new();
+        let mut buffer = [0; 8192];
+        loop {
+            stream.set_read_timeout(Some(remaining(until)?))?;
+            match stream.read(&mut buffer) {
+                Ok(0) => return Ok(response),
+                Ok(read) => response.extend_from_slice(&buffer[..read]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    };
+
+    match exchange() {
+        Ok(response) => decode(&response),
+        Err(ref e)
+            if deadline.is_some()
+                && (e.kind() == io::ErrorKind::TimedOut
+                    || e.kind() == io::ErrorKind::WouldBlock) =>
+        {
+            Err(ResponseError::DeadlineExceeded)
+        }
+        Err(e) => Err(ResponseError::SocketError(path.display().to_string(), e)),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn send(
+    path: &Path,
+    _: &Request,
+    _: &str,
+    _: Option<Instant>,
+) -> Result<(StatusCode, HeaderMap, String), ResponseError> {
+    Err(ResponseError::SocketError(
+        path.display().to_string(),
+        io::Error::new(
+            io::ErrorKind::Other,
+            "Unix domain sockets are only supported on Unix",
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, socket};
+    use crate::query::ResponseError;
+    use reqwest::StatusCode;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_socket() {
+        assert_eq!(
+            socket("http+unix://%2Fvar%2Frun%2Felasticsearch.sock/logs-*"),
+            Some(PathBuf::from("/var/run/elasticsearch.sock"))
+        );
+        assert_eq!(socket("http+unix:///logs-*"), None);
+        assert_eq!(socket("http://localhost:9200/"), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        let (status, headers, body) = decode(
+            b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n{}",
+        )
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "application/json");
+        assert_eq!(body, "{}");
+
+        let (status, _, body) = decode(
+            b"HTTP/1.1 401 Unauthorized\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n{\"a\r\n4\r\n\":1}\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, "{\"a\":1}");
+
+        assert!(decode(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n{}").is_err());
+        assert!(decode(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+
+    #[test]
+    fn test_decode_chunked() {
+        let (_, _, body) = decode(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\nA;ext=1\r\n0123456789\r\n0\r\nX-Trailer: 1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(body, "0123456789");
+
+        // The last chunk is missing or shorter than its size
+        for response in [
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n"[..],
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n{}\r\n0\r\n\r\n"[..],
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n{}\r\n0\r\n\r\n"[..],
+        ]
+        .iter()
+        {
+            assert!(decode(response).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_oversized() {
+        // Anything after the announced length is ignored
+        let (_, _, body) =
+            decode(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}{\"trailing\": 1}").unwrap();
+        assert_eq!(body, "{}");
+
+        // Lengths beyond the data or even the address space
+        assert!(decode(b"HTTP/1.1 200 OK\r\ncontent-length: 1048576\r\n\r\n{}").is_err());
+        assert!(
+            decode(b"HTTP/1.1 200 OK\r\ncontent-length: 99999999999999999999999\r\n\r\n{}")
+                .is_err()
+        );
+
+        assert!(decode(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n\xff\xfe").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send_deadline() {
+        use std::io::Write;
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+
+        let path = std::env::temp_dir().join(format!("50shades-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // Trickles a byte every 50ms, so each single read succeeds in time
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for _ in 0..20 {
+                if stream.write_all(b"H").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let request = reqwest::Client::new()
+            .get("http://localhost/_search")
+            .build()
+            .unwrap();
+        let started = Instant::now();
+        let result = super::send(
+            &path,
+            &request,
+            "",
+            Some(started + Duration::from_millis(200)),
+        );
+
+        assert!(match result {
+            Err(ResponseError::DeadlineExceeded) => true,
+            _ => false,
+        });
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
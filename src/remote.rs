@@ -0,0 +1,74 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use dirs;
+use failure::{Error, Fail};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Fail)]
+#[fail(display = "Could not fetch {} and no cached copy exists: {}", _0, _1)]
+pub struct FetchError(String, String);
+
+/// Whether `location` is to be fetched over HTTP rather than read from disk
+pub fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Where the last fetched copy of `url` is kept
+fn cache_path(url: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|path| {
+        path.join("50shades")
+            .join(utf8_percent_encode(url, NON_ALPHANUMERIC).to_string())
+    })
+}
+
+fn get(url: &str) -> Result<String, Error> {
+    let mut response = reqwest::get(url)?.error_for_status()?;
+    Ok(response.text()?)
+}
+
+/// Fetches `url`, caching the result. Falls back to the cached copy with a
+/// warning when the fetch fails.
+pub fn fetch(url: &str) -> Result<String, Error> {
+    let cache = cache_path(url);
+
+    match get(url) {
+        Ok(contents) => {
+            if let Some(ref path) = cache {
+                let written = path
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::write(path, &contents));
+
+                if let Err(e) = written {
+                    eprintln!("Could not cache {}: {}", url, e);
+                }
+            }
+
+            Ok(contents)
+        }
+        Err(e) => match cache.and_then(|path| fs::read_to_string(&path).ok()) {
+            Some(contents) => {
+                eprintln!("Warning: could not fetch {}, using cached copy: {}", url, e);
+                Ok(contents)
+            }
+            None => Err(FetchError(url.to_owned(), e.to_string()).into()),
+        },
+    }
+}
@@ -0,0 +1,232 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::output::Record;
+use failure::Fail;
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Fields consulted for a record's level when none is given explicitly
+const SEVERITY_FIELDS: &[&str] = &["level", "log.level", "severity"];
+
+#[derive(Debug, Fail)]
+#[fail(display = "Unknown severity: {}", _0)]
+pub struct SeverityError(String);
+
+/// Canonical severities for level values a source uses, keyed by their text
+pub type SeverityMap = HashMap<String, Severity>;
+
+/// Canonical severity scale, ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Debug => "debug",
+            Severity::Info => "info",
+            Severity::Notice => "notice",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Critical => "critical",
+            Severity::Alert => "alert",
+            Severity::Emergency => "emergency",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Severity {
+    type Err = SeverityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "debug" | "trace" => Ok(Severity::Debug),
+            "info" | "informational" => Ok(Severity::Info),
+            "notice" => Ok(Severity::Notice),
+            "warning" | "warn" => Ok(Severity::Warning),
+            "error" | "err" => Ok(Severity::Error),
+            "critical" | "crit" | "fatal" => Ok(Severity::Critical),
+            "alert" => Ok(Severity::Alert),
+            "emergency" | "emerg" => Ok(Severity::Emergency),
+            _ => Err(SeverityError(s.into())),
+        }
+    }
+}
+
+impl Serialize for Severity {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Severity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Severity {
+    pub const ALL: [Severity; 8] = [
+        Severity::Debug,
+        Severity::Info,
+        Severity::Notice,
+        Severity::Warning,
+        Severity::Error,
+        Severity::Critical,
+        Severity::Alert,
+        Severity::Emergency,
+    ];
+
+    /// Maps a syslog level (0 = emergency, 7 = debug) onto the scale.
+    pub fn from_syslog(level: u64) -> Option<Self> {
+        match level {
+            0 => Some(Severity::Emergency),
+            1 => Some(Severity::Alert),
+            2 => Some(Severity::Critical),
+            3 => Some(Severity::Error),
+            4 => Some(Severity::Warning),
+            5 => Some(Severity::Notice),
+            6 => Some(Severity::Info),
+            7 => Some(Severity::Debug),
+            _ => None,
+        }
+    }
+
+    /// The syslog level (0 = emergency, 7 = debug) of the severity.
+    pub fn syslog(self) -> u64 {
+        match self {
+            Severity::Emergency => 0,
+            Severity::Alert => 1,
+            Severity::Critical => 2,
+            Severity::Error => 3,
+            Severity::Warning => 4,
+            Severity::Notice => 5,
+            Severity::Info => 6,
+            Severity::Debug => 7,
+        }
+    }
+
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => n.as_u64().and_then(Severity::from_syslog),
+            Value::String(s) => s
+                .parse()
+                .ok()
+                .or_else(|| s.parse().ok().and_then(Severity::from_syslog)),
+            _ => None,
+        }
+    }
+}
+
+/// Looks up a possibly dotted field path in a record.
+pub fn lookup<'a>(record: &'a Record, path: &str) -> Option<&'a Value> {
+    record.get(path).or_else(|| {
+        let mut parts = path.split('.');
+        let first = record.get(parts.next()?)?;
+        parts.try_fold(first, |value, part| value.get(part))
+    })
+}
+
+/// Determines a record's severity from `field` or the well-known level fields.
+pub fn of(record: &Record, field: Option<&str>) -> Option<Severity> {
+    mapped(record, field, &SeverityMap::new())
+}
+
+/// Like `of`, but translating level values through `map` first.
+pub fn mapped(record: &Record, field: Option<&str>, map: &SeverityMap) -> Option<Severity> {
+    let severity = |value: &Value| {
+        let text = match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+
+        map.get(&text)
+            .copied()
+            .or_else(|| Severity::from_value(value))
+    };
+
+    match field {
+        Some(field) => lookup(record, field).and_then(severity),
+        None => SEVERITY_FIELDS
+            .iter()
+            .filter_map(|field| lookup(record, field))
+            .find_map(severity),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{mapped, of, Severity, SeverityMap};
+    use serde_json::json;
+
+    #[test]
+    fn test_severity_of() {
+        let graylog = json!({"level": 3});
+        let ecs = json!({"log": {"level": "WARN"}});
+        let custom = json!({"lvl": "info", "level": "nonsense"});
+
+        assert_eq!(
+            of(graylog.as_object().unwrap(), None),
+            Some(Severity::Error)
+        );
+        assert_eq!(of(ecs.as_object().unwrap(), None), Some(Severity::Warning));
+        assert_eq!(
+            of(custom.as_object().unwrap(), Some("lvl")),
+            Some(Severity::Info)
+        );
+        assert_eq!(of(custom.as_object().unwrap(), None), None);
+        assert!(Severity::Warning < Severity::Error);
+    }
+
+    #[test]
+    fn test_severity_mapped() {
+        let map: SeverityMap = vec![
+            (String::from("W"), Severity::Warning),
+            (String::from("3"), Severity::Debug),
+        ]
+        .into_iter()
+        .collect();
+
+        let record = |value| json!({ "level": value }).as_object().unwrap().clone();
+
+        assert_eq!(
+            mapped(&record(json!("W")), None, &map),
+            Some(Severity::Warning)
+        );
+        assert_eq!(mapped(&record(json!(3)), None, &map), Some(Severity::Debug));
+        assert_eq!(
+            mapped(&record(json!(4)), None, &map),
+            Some(Severity::Warning)
+        );
+    }
+}
@@ -14,15 +14,53 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use failure::Error;
+use chrono::prelude::*;
+use failure::{Error, Fail};
+use handlebars::template::{Parameter, Template, TemplateElement};
 use handlebars::{
-    Context, Handlebars, Helper, HelperResult, JsonRender, JsonValue as Json, Output,
-    RenderContext, RenderError,
+    Context, Handlebars, Helper, HelperDef, HelperResult, JsonRender, JsonValue as Json, Output,
+    RenderContext, RenderError, ScopedJson,
+};
+use rlua::{
+    Context as LuaContext, Error as LuaError, Function, Lua, MultiValue, RegistryKey,
+    Result as LuaResult, Table, Value,
 };
 use serde::Serialize;
+use serde_json::Map;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
 const TEMPLATE_KEY: &str = "50shades";
 
+/// Epoch values at or above this magnitude are taken to be milliseconds
+const EPOCH_MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+#[derive(Debug, Fail)]
+#[fail(display = "Could not load helper script {}: {}", _0, _1)]
+pub struct ScriptError(String, String);
+
+/// A Lua script run once, keeping the functions it defines in its own
+/// environment
+struct Compiled {
+    lua: Mutex<Lua>,
+    environment: RegistryKey,
+    functions: Vec<String>,
+}
+
+/// Helpers defined as functions of a Lua script, see `load_script`
+#[derive(Clone)]
+pub struct Script(Arc<Compiled>);
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Script").field(&self.0.functions).finish()
+    }
+}
+
 fn default_helper(
     helper: &Helper,
     _: &Handlebars,
@@ -47,22 +85,580 @@ fn default_helper(
     Ok(())
 }
 
-pub fn compile(template: &str) -> Result<Handlebars, Error> {
-    let mut handlebars = Handlebars::new();
+fn width_params(helper: &Helper, name: &str) -> Result<(String, usize), RenderError> {
+    match helper.params().as_slice() {
+        [value, width] => match width.value().as_u64() {
+            Some(width) => Ok((value.value().render(), width as usize)),
+            None => Err(RenderError::new(format!(
+                "`{}` helper width must be a non-negative integer",
+                name
+            ))),
+        },
+        _ => Err(RenderError::new(format!(
+            "`{}` helper must be invoked with two parameters, `value` and `width`",
+            name
+        ))),
+    }
+}
+
+/// Truncates or pads `value` to exactly `width` characters.
+fn fit(value: &str, width: usize, right: bool) -> String {
+    let value: String = value.chars().take(width).collect();
+    let padding = " ".repeat(width - value.chars().count());
+
+    if right {
+        padding + &value
+    } else {
+        value + &padding
+    }
+}
+
+fn pad_helper(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let (value, width) = width_params(helper, "pad")?;
+    out.write(&fit(&value, width, false))?;
+    Ok(())
+}
+
+fn align_helper(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let (value, width) = width_params(helper, "align")?;
+    out.write(&fit(&value, width, true))?;
+    Ok(())
+}
+
+/// Strips leading and trailing whitespace from a value.
+struct TrimHelper;
+
+impl HelperDef for TrimHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg>,
+    ) -> Result<Option<ScopedJson<'reg, 'rc>>, RenderError> {
+        let value = match helper.params().as_slice() {
+            [value] => value.value().render(),
+            _ => {
+                return Err(RenderError::new(
+                    "`trim` helper must be invoked with one parameter, `value`",
+                ))
+            }
+        };
+
+        Ok(Some(ScopedJson::Derived(Json::from(value.trim()))))
+    }
+}
+
+/// Splits a value into an array of strings at each occurrence of a separator.
+struct SplitHelper;
+
+impl HelperDef for SplitHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg>,
+    ) -> Result<Option<ScopedJson<'reg, 'rc>>, RenderError> {
+        let (value, separator) =
+            match helper.params().as_slice() {
+                [value, separator] => (value.value(), separator.render()),
+                _ => return Err(RenderError::new(
+                    "`split` helper must be invoked with two parameters, `value` and `separator`",
+                )),
+            };
+
+        if separator.is_empty() {
+            return Err(RenderError::new(
+                "`split` helper separator must not be empty",
+            ));
+        }
+
+        let parts: Vec<Json> = match value {
+            Json::Null => Vec::new(),
+            value => value.render().split(&separator).map(Json::from).collect(),
+        };
+
+        Ok(Some(ScopedJson::Derived(Json::Array(parts))))
+    }
+}
+
+/// Picks the element at a zero-based index from an array, or nothing if the
+/// index is out of range.
+struct NthHelper;
+
+impl HelperDef for NthHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg>,
+    ) -> Result<Option<ScopedJson<'reg, 'rc>>, RenderError> {
+        let (array, index) = match helper.params().as_slice() {
+            [array, index] => match (array.value(), index.value().as_u64()) {
+                (Json::Array(array), Some(index)) => (array, index as usize),
+                (Json::Array(_), None) => {
+                    return Err(RenderError::new(
+                        "`nth` helper index must be a non-negative integer",
+                    ))
+                }
+                _ => {
+                    return Err(RenderError::new(
+                        "`nth` helper must be invoked with an array",
+                    ))
+                }
+            },
+            _ => {
+                return Err(RenderError::new(
+                    "`nth` helper must be invoked with two parameters, `array` and `index`",
+                ))
+            }
+        };
+
+        Ok(Some(ScopedJson::Derived(
+            array.get(index).cloned().unwrap_or(Json::Null),
+        )))
+    }
+}
+
+/// Renders an array element, keeping nested structures as JSON.
+fn element(value: &Json) -> String {
+    match value {
+        Json::Object(_) | Json::Array(_) => value.to_string(),
+        value => value.render(),
+    }
+}
+
+/// Joins the elements of an array with a separator; other values are
+/// rendered as they are.
+fn join_helper(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let (value, separator) = match helper.params().as_slice() {
+        [value, separator] => (value.value(), separator.render()),
+        _ => {
+            return Err(RenderError::new(
+                "`join` helper must be invoked with two parameters, `value` and `separator`",
+            ))
+        }
+    };
+
+    match value {
+        Json::Array(array) => {
+            let elements: Vec<String> = array.iter().map(element).collect();
+            out.write(&elements.join(&separator))?
+        }
+        value => out.write(&value.render())?,
+    }
+
+    Ok(())
+}
+
+/// Converts an epoch timestamp in seconds or milliseconds to RFC 3339, or to
+/// the strftime `format` hash parameter if given.
+fn from_unix_helper(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let epoch = match helper.param(0).map(|param| param.value()) {
+        Some(Json::Number(n)) if n.is_i64() || n.is_u64() => n.as_i64(),
+        Some(Json::String(s)) => s.parse::<i64>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| RenderError::new("`from_unix` helper must be invoked with an integer"))?;
+
+    let unit = helper
+        .hash_get("unit")
+        .map(|unit| unit.value().render())
+        .unwrap_or_else(|| {
+            if epoch.abs() >= EPOCH_MILLIS_THRESHOLD {
+                String::from("ms")
+            } else {
+                String::from("s")
+            }
+        });
+
+    let millis = match unit.as_str() {
+        "s" => epoch.checked_mul(1000),
+        "ms" => Some(epoch),
+        _ => return Err(RenderError::new("`from_unix` helper unit must be s or ms")),
+    };
+
+    let datetime = millis
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .ok_or_else(|| RenderError::new("`from_unix` helper value is out of range"))?;
+
+    match helper.hash_get("format") {
+        Some(format) => {
+            let mut formatted = String::new();
+            write!(formatted, "{}", datetime.format(&format.value().render()))
+                .map_err(|_| RenderError::new("`from_unix` helper format is invalid"))?;
+            out.write(&formatted)?
+        }
+        None => out.write(&datetime.to_rfc3339_opts(SecondsFormat::Millis, true))?,
+    }
+
+    Ok(())
+}
+
+/// A helper parameter as a Lua value, with arrays and objects as tables
+fn lua_value<'lua>(context: LuaContext<'lua>, value: &Json) -> LuaResult<Value<'lua>> {
+    Ok(match value {
+        Json::Null => Value::Nil,
+        Json::Bool(value) => Value::Boolean(*value),
+        Json::Number(value) => match value.as_i64() {
+            Some(value) => Value::Integer(value),
+            None => Value::Number(value.as_f64().unwrap_or_default()),
+        },
+        Json::String(value) => Value::String(context.create_string(value)?),
+        Json::Array(values) => {
+            let table = context.create_table()?;
+            for (i, value) in values.iter().enumerate() {
+                table.raw_set(i + 1, lua_value(context, value)?)?;
+            }
+            Value::Table(table)
+        }
+        Json::Object(values) => {
+            let table = context.create_table()?;
+            for (key, value) in values.iter() {
+                table.raw_set(key.as_str(), lua_value(context, value)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+impl Script {
+    /// Calls the script's function `name` with `params`, returning its result
+    /// as text, which is empty for nil.
+    fn call(&self, name: &str, params: &[&Json]) -> LuaResult<String> {
+        let lua = self.0.lua.lock().unwrap();
+
+        lua.context(|context| {
+            let environment: Table = context.registry_value(&self.0.environment)?;
+            let function: Function = environment.get(name)?;
+            let params = params
+                .iter()
+                .map(|param| lua_value(context, param))
+                .collect::<LuaResult<Vec<_>>>()?;
+
+            match function.call(MultiValue::from_vec(params))? {
+                Value::Nil => Ok(String::new()),
+                Value::Boolean(value) => Ok(value.to_string()),
+                Value::Integer(value) => Ok(value.to_string()),
+                Value::Number(value) => Ok(value.to_string()),
+                Value::String(value) => Ok(value.to_str()?.to_owned()),
+                _ => Err(LuaError::RuntimeError(String::from(
+                    "result is not a string, number, boolean or nil",
+                ))),
+            }
+        })
+    }
+}
+
+/// Calls the script function of the same name with the helper's parameters.
+struct ScriptHelper {
+    script: Script,
+    name: String,
+}
+
+impl HelperDef for ScriptHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg>,
+    ) -> Result<Option<ScopedJson<'reg, 'rc>>, RenderError> {
+        let params: Vec<&Json> = helper.params().iter().map(|param| param.value()).collect();
+
+        let result = self
+            .script
+            .call(&self.name, &params)
+            .map_err(|e| RenderError::new(format!("`{}` helper failed: {}", self.name, e)))?;
+
+        Ok(Some(ScopedJson::Derived(Json::from(result))))
+    }
+}
+
+/// Runs the Lua script at `path` once, so that the functions it defines can
+/// be used as helpers by templates compiled with it. Functions named after a
+/// built-in helper are left out.
+pub fn load_script(path: &Path) -> Result<Script, Error> {
+    let error = |message: String| ScriptError(path.display().to_string(), message);
+
+    let source = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    let lua = Lua::new();
+
+    let (environment, defined) = lua
+        .context(|context| -> LuaResult<(RegistryKey, Vec<String>)> {
+            // Globals the script defines land in its environment, which falls
+            // back to the standard library for everything else
+            let environment = context.create_table()?;
+            let fallback = context.create_table()?;
+            fallback.set("__index", context.globals())?;
+            environment.set_metatable(Some(fallback));
+
+            context
+                .load(&source)
+                .set_name(&path.display().to_string())?
+                .set_environment(environment.clone())?
+                .exec()?;
+
+            let mut defined = Vec::new();
+            for pair in environment.clone().pairs::<Value, Value>() {
+                if let (Value::String(name), Value::Function(_)) = pair? {
+                    defined.push(name.to_str()?.to_owned());
+                }
+            }
+
+            Ok((context.create_registry_value(environment)?, defined))
+        })
+        .map_err(|e| error(e.to_string()))?;
+
+    let mut builtin = Handlebars::new();
+    register_helpers(&mut builtin);
+
+    let mut functions = Vec::new();
+    for name in defined {
+        if builtin.get_helper(&name).is_some() {
+            eprintln!(
+                "Warning: helper script function {} is ignored, as {} is a built-in helper",
+                name, name
+            );
+        } else {
+            functions.push(name);
+        }
+    }
+    functions.sort();
+
+    Ok(Script(Arc::new(Compiled {
+        lua: Mutex::new(lua),
+        environment,
+        functions,
+    })))
+}
+
+fn register_helpers(handlebars: &mut Handlebars) {
     handlebars.register_helper("default", Box::new(default_helper));
+    handlebars.register_helper("pad", Box::new(pad_helper));
+    handlebars.register_helper("align", Box::new(align_helper));
+    handlebars.register_helper("from_unix", Box::new(from_unix_helper));
+    handlebars.register_helper("join", Box::new(join_helper));
+    handlebars.register_helper("trim", Box::new(TrimHelper));
+    handlebars.register_helper("split", Box::new(SplitHelper));
+    handlebars.register_helper("nth", Box::new(NthHelper));
+}
+
+/// Compiles `template` with the built-in helpers and those of `script`, if
+/// any.
+pub fn compile(template: &str, script: Option<&Script>) -> Result<Handlebars, Error> {
+    let mut handlebars = Handlebars::new();
+    register_helpers(&mut handlebars);
+
+    if let Some(script) = script {
+        for name in script.0.functions.iter() {
+            handlebars.register_helper(
+                name,
+                Box::new(ScriptHelper {
+                    script: script.clone(),
+                    name: name.clone(),
+                }),
+            );
+        }
+    }
+
     handlebars.register_template_string(TEMPLATE_KEY, template)?;
     Ok(handlebars)
 }
 
+fn parameter_fields(handlebars: &Handlebars, parameter: &Parameter, fields: &mut BTreeSet<String>) {
+    match parameter {
+        Parameter::Name(name)
+            if !name.starts_with('@') && handlebars.get_helper(name).is_none() =>
+        {
+            let name = name
+                .trim_start_matches("this.")
+                .trim_start_matches("./")
+                .replace('/', ".")
+                .replace(|c| c == '[' || c == ']', "");
+            if !name.is_empty() && name != "this" {
+                fields.insert(name);
+            }
+        }
+        Parameter::Subexpression(subexpression) => {
+            element_fields(handlebars, subexpression.as_element(), fields)
+        }
+        _ => (),
+    }
+}
+
+fn element_fields(
+    handlebars: &Handlebars,
+    element: &TemplateElement,
+    fields: &mut BTreeSet<String>,
+) {
+    match element {
+        TemplateElement::HTMLExpression(parameter) => {
+            parameter_fields(handlebars, parameter, fields)
+        }
+        TemplateElement::Expression(helper) | TemplateElement::HelperBlock(helper) => {
+            if helper.params.is_empty() && helper.hash.is_empty() {
+                parameter_fields(handlebars, &helper.name, fields);
+            }
+
+            for parameter in helper.params.iter().chain(helper.hash.values()) {
+                parameter_fields(handlebars, parameter, fields);
+            }
+
+            for template in helper.template.iter().chain(helper.inverse.iter()) {
+                template_fields(handlebars, template, fields);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn template_fields(handlebars: &Handlebars, template: &Template, fields: &mut BTreeSet<String>) {
+    for element in template.elements.iter() {
+        element_fields(handlebars, element, fields);
+    }
+}
+
+/// Fields a template outputs directly, as `{{field}}` or `{{{field}}}`, and
+/// those block helpers such as `#if` test.
+fn output_fields(
+    handlebars: &Handlebars,
+    template: &Template,
+    fields: &mut BTreeSet<String>,
+    tested: &mut BTreeSet<String>,
+) {
+    for element in template.elements.iter() {
+        match element {
+            TemplateElement::HTMLExpression(parameter) => {
+                parameter_fields(handlebars, parameter, fields)
+            }
+            TemplateElement::Expression(helper)
+                if helper.params.is_empty() && helper.hash.is_empty() =>
+            {
+                parameter_fields(handlebars, &helper.name, fields)
+            }
+            TemplateElement::HelperBlock(helper) => {
+                for parameter in helper.params.iter() {
+                    parameter_fields(handlebars, parameter, tested);
+                }
+
+                for template in helper.template.iter().chain(helper.inverse.iter()) {
+                    output_fields(handlebars, template, fields, tested);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Sets the dotted `path` in `data` to `text` unless it holds a value other
+/// than null or an empty string.
+fn fill(data: &mut Map<String, Json>, path: &str, text: &str) {
+    let mut parts: Vec<&str> = path.split('.').collect();
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return,
+    };
+
+    let mut object = data;
+    for part in parts {
+        let value = object
+            .entry(part)
+            .or_insert_with(|| Json::Object(Map::new()));
+        if value.is_null() {
+            *value = Json::Object(Map::new());
+        }
+
+        object = match value {
+            Json::Object(object) => object,
+            _ => return,
+        };
+    }
+
+    let empty = match object.get(last) {
+        None | Some(Json::Null) => true,
+        Some(Json::String(s)) => s.is_empty(),
+        Some(_) => false,
+    };
+
+    if empty {
+        object.insert(last.to_owned(), Json::from(text));
+    }
+}
+
+/// Field paths referenced by a compiled template, in dotted notation.
+pub fn fields(handlebars: &Handlebars) -> Vec<String> {
+    let mut fields = BTreeSet::new();
+
+    if let Some(template) = handlebars.get_template(TEMPLATE_KEY) {
+        template_fields(handlebars, template, &mut fields);
+    }
+
+    fields.into_iter().collect()
+}
+
 pub fn render<S: Serialize>(handlebars: &Handlebars, data: &S) -> Result<String, Error> {
     Ok(handlebars.render(TEMPLATE_KEY, data)?)
 }
 
+/// Like `render`, but showing `text` for fields the template outputs directly
+/// that are missing, null or empty. Fields tested by block helpers are left
+/// alone, so that `{{#if field}}` still sees them missing.
+pub fn render_or(
+    handlebars: &Handlebars,
+    data: &Map<String, Json>,
+    text: &str,
+) -> Result<String, Error> {
+    let mut fields = BTreeSet::new();
+    let mut tested = BTreeSet::new();
+    if let Some(template) = handlebars.get_template(TEMPLATE_KEY) {
+        output_fields(handlebars, template, &mut fields, &mut tested);
+    }
+
+    let mut data = data.clone();
+    for field in fields.difference(&tested) {
+        fill(&mut data, field, text);
+    }
+
+    render(handlebars, &data)
+}
+
 #[cfg(test)]
 mod test {
-    use super::default_helper;
+    use super::{
+        align_helper, compile, default_helper, fields, from_unix_helper, join_helper, load_script,
+        pad_helper, render_or,
+    };
     use handlebars::Handlebars;
     use std::collections::HashMap;
+    use std::env;
+    use std::fs;
 
     #[test]
     fn test_default_helper() {
@@ -89,4 +685,204 @@ mod test {
         assert!(r.render("c", &context).is_err());
         assert!(r.render("d", &context).is_err());
     }
+
+    #[test]
+    fn test_pad_helpers() {
+        let mut r = Handlebars::new();
+
+        r.register_helper("pad", Box::new(pad_helper));
+        r.register_helper("align", Box::new(align_helper));
+
+        assert!(r.register_template_string("a", "[{{pad foo 6}}]").is_ok());
+        assert!(r.register_template_string("b", "[{{align foo 6}}]").is_ok());
+        assert!(r.register_template_string("c", "[{{pad foo 2}}]").is_ok());
+        assert!(r
+            .register_template_string("d", "[{{align long 3}}]")
+            .is_ok());
+        assert!(r
+            .register_template_string("e", "[{{pad missing 2}}]")
+            .is_ok());
+        assert!(r.register_template_string("f", "{{pad foo}}").is_ok());
+        assert!(r.register_template_string("g", "{{pad foo \"x\"}}").is_ok());
+
+        let mut context = HashMap::<&str, &str>::new();
+        context.insert("foo", "bär");
+        context.insert("long", "überlang");
+
+        assert_eq!(r.render("a", &context).unwrap(), "[bär   ]");
+        assert_eq!(r.render("b", &context).unwrap(), "[   bär]");
+        assert_eq!(r.render("c", &context).unwrap(), "[bä]");
+        assert_eq!(r.render("d", &context).unwrap(), "[übe]");
+        assert_eq!(r.render("e", &context).unwrap(), "[  ]");
+        assert!(r.render("f", &context).is_err());
+        assert!(r.render("g", &context).is_err());
+    }
+
+    #[test]
+    fn test_from_unix_helper() {
+        let mut r = Handlebars::new();
+
+        r.register_helper("from_unix", Box::new(from_unix_helper));
+
+        assert!(r.register_template_string("a", "{{from_unix s}}").is_ok());
+        assert!(r.register_template_string("b", "{{from_unix ms}}").is_ok());
+        assert!(r
+            .register_template_string("c", "{{from_unix s unit=\"ms\"}}")
+            .is_ok());
+        assert!(r
+            .register_template_string("d", "{{from_unix s format=\"%H:%M\"}}")
+            .is_ok());
+        assert!(r
+            .register_template_string("e", "{{from_unix text}}")
+            .is_ok());
+
+        let context = serde_json::json!({
+            "s": 1_569_931_200,
+            "ms": 1_569_931_200_123i64,
+            "text": "yesterday"
+        });
+
+        assert_eq!(r.render("a", &context).unwrap(), "2019-10-01T12:00:00.000Z");
+        assert_eq!(r.render("b", &context).unwrap(), "2019-10-01T12:00:00.123Z");
+        assert_eq!(r.render("c", &context).unwrap(), "1970-01-19T04:05:31.200Z");
+        assert_eq!(r.render("d", &context).unwrap(), "12:00");
+        assert!(r.render("e", &context).is_err());
+    }
+
+    #[test]
+    fn test_join_helper() {
+        let mut r = Handlebars::new();
+
+        r.register_helper("join", Box::new(join_helper));
+
+        let context = serde_json::json!({
+            "tags": ["web", "prod"],
+            "single": ["web"],
+            "empty": [],
+            "spans": [{"id": 1}, "x", [2]],
+            "host": "web-1"
+        });
+        let render = |template: &str| r.render_template(template, &context).ok();
+
+        assert_eq!(
+            render("{{join tags \", \"}}"),
+            Some(String::from("web, prod"))
+        );
+        assert_eq!(render("{{join single \", \"}}"), Some(String::from("web")));
+        assert_eq!(render("[{{join empty \", \"}}]"), Some(String::from("[]")));
+        assert_eq!(
+            render("{{join spans \"|\"}}"),
+            Some(String::from("{\"id\":1}|x|[2]"))
+        );
+        assert_eq!(render("{{join host \", \"}}"), Some(String::from("web-1")));
+        assert_eq!(
+            render("[{{join missing \", \"}}]"),
+            Some(String::from("[]"))
+        );
+        assert!(render("{{join tags}}").is_none());
+    }
+
+    #[test]
+    fn test_fields() {
+        let template = r#"{{from_unix ts format="%H"}} [{{default container_name "-"}}] {{{message}}} {{#if log.level}}{{pad (lookup this "other") 5}}{{/if}} {{@index}}"#;
+
+        assert_eq!(
+            fields(&compile(template, None).unwrap()),
+            vec!["container_name", "log.level", "message", "ts"]
+        );
+    }
+
+    #[test]
+    fn test_render_or() {
+        let r = compile(
+            "{{level}} {{{host.name}}} [{{message}}] {{#if user}}{{user}}{{/if}} {{default tag \"x\"}}",
+            None,
+        )
+        .unwrap();
+        let render =
+            |data: serde_json::Value| render_or(&r, data.as_object().unwrap(), "-").unwrap();
+
+        assert_eq!(
+            render(serde_json::json!({"level": 3, "host": {"name": "web-1"}, "message": "hi"})),
+            "3 web-1 [hi]  x"
+        );
+        assert_eq!(
+            render(serde_json::json!({"level": null, "message": "", "user": "root"})),
+            "- - [-] root x"
+        );
+    }
+
+    #[test]
+    fn test_string_helpers() {
+        let r = compile("", None).unwrap();
+        let context = serde_json::json!({
+            "message": "  GET /health 200  ",
+            "words": ["a", "b"],
+            "count": 3
+        });
+        let render = |template: &str| r.render_template(template, &context).ok();
+
+        assert_eq!(
+            render("[{{trim message}}]"),
+            Some(String::from("[GET /health 200]"))
+        );
+        assert_eq!(
+            render("{{nth (split (trim message) \" \") 1}}"),
+            Some(String::from("/health"))
+        );
+        assert_eq!(
+            render("{{nth (split message \" \") 42}}"),
+            Some(String::new())
+        );
+        assert_eq!(
+            render("{{nth (split missing \",\") 0}}"),
+            Some(String::new())
+        );
+        assert_eq!(render("{{nth words 0}}"), Some(String::from("a")));
+        assert!(render("{{nth count 0}}").is_none());
+        assert!(render("{{nth words \"x\"}}").is_none());
+        assert!(render("{{split message}}").is_none());
+        assert!(render("{{split message \"\"}}").is_none());
+        assert!(render("{{trim}}").is_none());
+    }
+
+    #[test]
+    fn test_script_helpers() {
+        let path = env::temp_dir().join(format!("50shades-helpers-{}.lua", std::process::id()));
+        fs::write(
+            &path,
+            "-- function commented(value) is not a helper\n\
+             local calls = 0\n\n\
+             function tagged(tag, level)\n    return \"<\" .. tag .. \":\" .. (level + 1) .. \">\"\nend\n\n\
+             function counted()\n    calls = calls + 1\n    return calls\nend\n\n\
+             function trim(value)\n    return \"replaced\"\nend\n",
+        )
+        .unwrap();
+        let loaded = load_script(&path);
+        fs::write(&path, "function broken(value)").unwrap();
+        let broken = load_script(&path);
+        fs::remove_file(&path).unwrap();
+
+        let script = loaded.unwrap();
+        assert_eq!(script.0.functions, vec!["counted", "tagged"]);
+        assert!(broken.is_err());
+
+        let r = compile("", Some(&script)).unwrap();
+        let context = serde_json::json!({"host": "web-1", "level": 3});
+        let render = |template: &str| r.render_template(template, &context).ok();
+
+        assert_eq!(
+            render("{{tagged host level}}"),
+            Some(String::from("<web-1:4>"))
+        );
+        assert_eq!(render("{{trim \" a \"}}"), Some(String::from("a")));
+        assert!(render("{{tagged host}}").is_none());
+        assert!(render("{{commented host}}").is_none());
+        assert_eq!(render("{{counted}} {{counted}}"), Some(String::from("1 2")));
+
+        assert!(compile("{{tagged host level}}", None)
+            .unwrap()
+            .render_template("{{tagged host level}}", &context)
+            .is_err());
+    }
 }
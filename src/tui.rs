@@ -0,0 +1,304 @@
+// This file is part of 50shades.
+//
+// Copyright 2019 Communicatio.Systems GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{ExecutableCommand, QueueableCommand};
+use std::io::{self, Stdout, Write};
+
+/// Columns a tab advances to the next multiple of
+const TAB_WIDTH: usize = 4;
+
+const HELP: &str = "q quit  / filter  j/k up/down  space/b page  g/G top/bottom";
+
+/// Calls `f` for each character of `line`, with escape sequences passed
+/// whole and flagged.
+fn scan<F: FnMut(&str, bool)>(line: &str, mut f: F) {
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c != '\x1b' {
+            f(&line[start..start + c.len_utf8()], false);
+            continue;
+        }
+
+        let mut end = start + 1;
+        if let Some(&(i, '[')) = chars.peek() {
+            chars.next();
+            end = i + 1;
+            // Parameters up to the final byte of a CSI sequence
+            for (i, c) in chars.by_ref() {
+                end = i + c.len_utf8();
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+        f(&line[start..end], true);
+    }
+}
+
+/// `line` without escape sequences
+fn plain(line: &str) -> String {
+    let mut plain = String::new();
+    scan(line, |s, escape| {
+        if !escape {
+            plain.push_str(s);
+        }
+    });
+    plain
+}
+
+/// `line` cut to `width` columns with tabs expanded, keeping its escape
+/// sequences and resetting them at the end.
+fn fit(line: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut column = 0;
+    let mut styled = false;
+
+    scan(line, |s, escape| {
+        if escape {
+            fitted.push_str(s);
+            styled = true;
+        } else if s == "\t" {
+            let next = (column / TAB_WIDTH + 1) * TAB_WIDTH;
+            while column < next.min(width) {
+                fitted.push(' ');
+                column += 1;
+            }
+        } else if column < width && !s.chars().any(char::is_control) {
+            fitted.push_str(s);
+            column += 1;
+        }
+    });
+
+    if styled {
+        fitted.push_str("\x1b[0m");
+    }
+    fitted
+}
+
+/// Indexes of the lines containing `filter`, ignoring case and styling
+fn matching(lines: &[String], filter: &str) -> Vec<usize> {
+    let filter = filter.to_lowercase();
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| filter.is_empty() || plain(line).to_lowercase().contains(&filter))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped
+struct Terminal {
+    out: Stdout,
+}
+
+impl Terminal {
+    fn open() -> crossterm::Result<Self> {
+        terminal::enable_raw_mode()?;
+
+        let mut terminal = Terminal { out: io::stdout() };
+        terminal.out.execute(EnterAlternateScreen)?.execute(Hide)?;
+        Ok(terminal)
+    }
+
+    /// Rows and columns, falling back to 24x80
+    fn size(&self) -> (usize, usize) {
+        match terminal::size() {
+            Ok((columns, rows)) if rows > 0 && columns > 0 => (rows as usize, columns as usize),
+            _ => (24, 80),
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self
+            .out
+            .execute(Show)
+            .and_then(|out| out.execute(LeaveAlternateScreen));
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Position and filter of the view onto the lines
+struct View<'a> {
+    lines: &'a [String],
+    filter: String,
+    /// Filter being typed after /, if any
+    prompt: Option<String>,
+    visible: Vec<usize>,
+    top: usize,
+}
+
+impl<'a> View<'a> {
+    fn new(lines: &'a [String]) -> Self {
+        View {
+            lines,
+            filter: String::new(),
+            prompt: None,
+            visible: matching(lines, ""),
+            top: 0,
+        }
+    }
+
+    fn scroll(&mut self, by: isize, rows: usize) {
+        let last = self.visible.len().saturating_sub(rows);
+        let top = self.top as isize + by;
+        self.top = if top < 0 { 0 } else { (top as usize).min(last) };
+    }
+
+    fn set_filter(&mut self, filter: String) {
+        self.visible = matching(self.lines, &filter);
+        self.filter = filter;
+        self.top = 0;
+    }
+
+    /// Acts on `key` with `rows` lines shown, returning whether to go on.
+    fn handle(&mut self, key: KeyEvent, rows: usize) -> bool {
+        let page = rows.max(1) as isize;
+        let interrupt =
+            key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+
+        if let Some(mut prompt) = self.prompt.take() {
+            match key.code {
+                _ if interrupt => (),
+                KeyCode::Enter => self.set_filter(prompt),
+                KeyCode::Esc => (),
+                KeyCode::Backspace => {
+                    prompt.pop();
+                    self.prompt = Some(prompt);
+                }
+                KeyCode::Char(c) => {
+                    prompt.push(c);
+                    self.prompt = Some(prompt);
+                }
+                _ => self.prompt = Some(prompt),
+            }
+            return true;
+        }
+
+        match key.code {
+            _ if interrupt => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.scroll(-1, rows),
+            KeyCode::Down | KeyCode::Enter | KeyCode::Char('j') => self.scroll(1, rows),
+            KeyCode::PageUp | KeyCode::Char('b') => self.scroll(-page, rows),
+            KeyCode::PageDown | KeyCode::Char(' ') => self.scroll(page, rows),
+            KeyCode::Home | KeyCode::Char('g') => self.top = 0,
+            KeyCode::End | KeyCode::Char('G') => self.scroll(self.visible.len() as isize, rows),
+            KeyCode::Esc => self.set_filter(String::new()),
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('/') => self.prompt = Some(String::new()),
+            _ => (),
+        }
+
+        true
+    }
+
+    fn status(&self, rows: usize) -> String {
+        if let Some(ref prompt) = self.prompt {
+            return format!("/{}", prompt);
+        }
+
+        let shown = if self.visible.is_empty() {
+            String::from("no lines")
+        } else {
+            format!(
+                "lines {}-{} of {}",
+                self.top + 1,
+                (self.top + rows).min(self.visible.len()),
+                self.visible.len()
+            )
+        };
+
+        if self.filter.is_empty() {
+            format!("{}  {}", shown, HELP)
+        } else {
+            format!("{} matching {}  {}", shown, self.filter, HELP)
+        }
+    }
+
+    fn draw<W: Write>(&self, out: &mut W, rows: usize, width: usize) -> crossterm::Result<()> {
+        for row in 0..rows {
+            out.queue(MoveTo(0, row as u16))?
+                .queue(Clear(ClearType::CurrentLine))?;
+            if let Some(&i) = self.visible.get(self.top + row) {
+                out.queue(Print(fit(&self.lines[i], width)))?;
+            }
+        }
+
+        out.queue(MoveTo(0, rows as u16))?
+            .queue(Clear(ClearType::CurrentLine))?
+            .queue(SetAttribute(Attribute::Reverse))?
+            .queue(Print(fit(&self.status(rows), width)))?
+            .queue(SetAttribute(Attribute::Reset))?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Shows `lines` on the terminal, scrollable and filterable, until the user
+/// quits.
+pub fn browse(lines: &[String]) -> crossterm::Result<()> {
+    let mut terminal = Terminal::open()?;
+    let mut view = View::new(lines);
+
+    loop {
+        let (height, width) = terminal.size();
+        // The last row holds the status line
+        let rows = height.saturating_sub(1);
+        view.scroll(0, rows);
+        view.draw(&mut terminal.out, rows, width)?;
+
+        // Resizes just redraw
+        if let Event::Key(key) = event::read()? {
+            if !view.handle(key, rows) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fit, matching, plain};
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("disk full", 4), "disk");
+        assert_eq!(fit("a\tb", 10), "a   b");
+        assert_eq!(
+            fit("\x1b[1;33merror\x1b[0m: disk full", 7),
+            "\x1b[1;33merror\x1b[0m: \x1b[0m"
+        );
+        assert_eq!(plain("\x1b[31mdisk\x1b[0m full"), "disk full");
+    }
+
+    #[test]
+    fn test_matching() {
+        let lines = vec![
+            String::from("Disk full"),
+            String::from("\x1b[31mdisk\x1b[0m error"),
+            String::from("network down"),
+        ];
+        assert_eq!(matching(&lines, ""), vec![0, 1, 2]);
+        assert_eq!(matching(&lines, "DISK"), vec![0, 1]);
+        assert_eq!(matching(&lines, "m"), Vec::<usize>::new());
+    }
+}